@@ -9,7 +9,7 @@ mod tests {
     use primitivemap::{bucket::BUCKET_LIST_SIZE, PrimitiveMap};
 
     use indexmap::IndexMap;
-    use std::{collections::HashMap, u16, u32, u64, u8};
+    use std::collections::HashMap;
     use test;
 
     #[bench]
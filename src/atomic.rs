@@ -0,0 +1,62 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bucket::{SmallVecBucket, SmallVecBucketList};
+use hash::{DefaultHasher, Hasher};
+use kv::Key;
+use PrimitiveMap;
+
+type SlotMap<K> = PrimitiveMap<
+    K,
+    usize,
+    SmallVecBucket<K, usize>,
+    SmallVecBucketList<K, usize>,
+    DefaultHasher<K>,
+>;
+
+/// Counters for a set of keys fixed at construction. The key layout never
+/// changes afterwards, so updates only need `&self`.
+pub struct AtomicPrimitiveMap<K: Key>
+where
+    DefaultHasher<K>: Hasher<K>,
+{
+    slots: SlotMap<K>,
+    counters: Vec<AtomicU64>,
+}
+
+impl<K: Key> AtomicPrimitiveMap<K>
+where
+    DefaultHasher<K>: Hasher<K>,
+{
+    pub fn new<I: IntoIterator<Item = K>>(keys: I) -> Self {
+        let mut slots = SlotMap::dynamic();
+        let mut counters = Vec::new();
+        for key in keys {
+            slots.get_or_insert_with_key(key, |_| {
+                counters.push(AtomicU64::new(0));
+                counters.len() - 1
+            });
+        }
+        AtomicPrimitiveMap { slots, counters }
+    }
+
+    pub fn get(&self, key: K) -> Option<&AtomicU64> {
+        self.slots.get(key).map(|slot| &self.counters[slot])
+    }
+
+    pub fn load(&self, key: K, order: Ordering) -> Option<u64> {
+        self.get(key).map(|counter| counter.load(order))
+    }
+
+    pub fn increment(&self, key: K) -> Option<u64> {
+        self.get(key)
+            .map(|counter| counter.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+}
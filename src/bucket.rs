@@ -1,13 +1,133 @@
+use hash::Hash;
 use kv::{Key, Value};
 use smallvec::SmallVec;
+use std::array;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::mem;
+use std::vec;
 
 pub const BUCKET_SIZE: usize = 2;
 pub const BUCKET_LIST_SIZE: usize = 2048;
 
+/// Number of `ArrayBucket`s needed to hold `n` entries at a load factor of
+/// `load_numerator / load_denominator`, usable as an array length.
+pub const fn capacity_for_const(n: usize, load_numerator: usize, load_denominator: usize) -> usize {
+    let slots = (n * load_denominator).div_ceil(load_numerator);
+    slots.div_ceil(BUCKET_SIZE)
+}
+
+/// Storage for the entries whose keys hash to one address. Apart from
+/// `extend_from`, the methods that add entries don't check
+/// `reached_max_capacity`, callers that probe check it before pushing.
 pub trait Bucket<K: Key, V: Value>: Sized {
+    /// Whether `find_mut` gets the position of an entry as part of the
+    /// lookup. The map removes entries of buckets where it doesn't by key.
+    const CHEAP_POSITIONS: bool = true;
+
     fn new() -> Self;
     fn push(&mut self, key: K, value: V);
     fn get(&self, key: K) -> Option<V>;
+    fn get_mut(&mut self, key: K) -> Option<&mut V>;
+    /// Like `get_mut`, but also returns the position of the entry within
+    /// the bucket.
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut V)>;
+    fn take(&mut self, key: K) -> Option<(K, V)>;
+    /// Removes the entry at a position returned by `find_mut` or
+    /// `insert_indexed`. Panics if no entry is at `index`.
+    fn remove_at(&mut self, index: usize) -> (K, V);
+    /// Finds `key` or pushes it with the value from `f` in a single scan.
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V;
+    fn clear(&mut self);
+    fn is_empty(&self) -> bool;
+    fn capacity(&self) -> usize;
+    fn reached_max_capacity(&self) -> bool;
+    fn iter(&self) -> impl Iterator<Item = (K, V)>;
+    /// Keeps the entries `f` returns true for and returns how many were removed.
+    fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, f: F) -> usize;
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut V)>
+    where
+        V: 'a;
+
+    /// Looks at the keys only, for buckets that store values apart.
+    fn contains(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn reserve(&mut self, _additional: usize) {}
+    fn shrink_to_fit(&mut self) {}
+
+    /// One flag per slot telling whether it holds a live entry. Buckets keep
+    /// their entries packed at the front, so the live slots come first.
+    fn occupancy(&self) -> impl Iterator<Item = bool> {
+        let live = self.iter().count();
+        (0..self.capacity()).map(move |slot| slot < live)
+    }
+
+    /// Replaces the value of `key` in place or pushes a new entry, and
+    /// returns the position of the entry along with the replaced value.
+    fn insert_indexed(&mut self, key: K, value: V) -> (usize, Option<V>) {
+        if let Some((idx, slot)) = self.find_mut(key) {
+            return (idx, Some(mem::replace(slot, value)));
+        }
+        self.push(key, value);
+        (self.find_mut(key).unwrap().0, None)
+    }
+
+    /// Finds `key` or pushes it with a default value, and returns its
+    /// position along with whether it was pushed.
+    fn entry_index(&mut self, key: K) -> (usize, bool) {
+        if let Some((idx, _)) = self.find_mut(key) {
+            return (idx, false);
+        }
+        self.push(key, V::default());
+        (self.find_mut(key).unwrap().0, true)
+    }
+
+    /// Removes the entries `f` returns true for and returns them.
+    fn drain_filter<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) -> vec::IntoIter<(K, V)> {
+        let mut drained = Vec::new();
+        self.retain(|key, value| {
+            if f(key, value) {
+                drained.push((key, *value));
+                false
+            } else {
+                true
+            }
+        });
+        drained.into_iter()
+    }
+
+    /// Compares the entries regardless of their order within the bucket.
+    fn eq_entries(&self, other: &Self) -> bool
+    where
+        V: PartialEq,
+    {
+        self.iter().count() == other.iter().count()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+
+    /// Inserts every entry, a key that is already present gets the later
+    /// value.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) {
+        for (key, value) in entries {
+            self.insert_indexed(key, value);
+        }
+    }
+
+    /// Pushes entries until the bucket reaches its max capacity, leaving the
+    /// rest in `entries`.
+    fn extend_from<I: Iterator<Item = (K, V)>>(&mut self, entries: &mut I) {
+        while !self.reached_max_capacity() {
+            match entries.next() {
+                Some((key, value)) => self.push(key, value),
+                None => return,
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -34,6 +154,103 @@ impl<K: Key, V: Value> Bucket<K, V> for SmallVecBucket<K, V> {
             .find(|&&(k, _)| k == key)
             .map(|&(_, v)| v)
     }
+
+    #[inline]
+    fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        self.members
+            .iter_mut()
+            .find(|&&mut (k, _)| k == key)
+            .map(|&mut (_, ref mut v)| v)
+    }
+
+    #[inline]
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut V)> {
+        self.members
+            .iter_mut()
+            .enumerate()
+            .find(|&(_, &mut (k, _))| k == key)
+            .map(|(idx, &mut (_, ref mut v))| (idx, v))
+    }
+
+    #[inline]
+    fn take(&mut self, key: K) -> Option<(K, V)> {
+        self.members
+            .iter()
+            .position(|&(k, _)| k == key)
+            .map(|idx| self.remove_at(idx))
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> (K, V) {
+        self.members.swap_remove(index)
+    }
+
+    #[inline]
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        let idx = match self.members.iter().position(|&(k, _)| k == key) {
+            Some(idx) => idx,
+            None => {
+                self.members.push((key, f()));
+                self.members.len() - 1
+            }
+        };
+        &mut self.members[idx].1
+    }
+
+    #[inline]
+    fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let len = self.members.len();
+        self.members.retain(|&mut (k, ref mut v)| f(k, v));
+        len - self.members.len()
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.members.clear()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.members.capacity()
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        self.members.reserve(additional)
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.members.shrink_to_fit()
+    }
+
+    #[inline]
+    fn reached_max_capacity(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.members.iter().cloned()
+    }
+
+    #[inline]
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut V)>
+    where
+        V: 'a,
+    {
+        self.members.iter_mut().map(|&mut (k, ref mut v)| (k, v))
+    }
+
+    #[inline]
+    fn extend_from<I: Iterator<Item = (K, V)>>(&mut self, entries: &mut I) {
+        self.members.extend(entries)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -65,67 +282,1054 @@ impl<K: Key, V: Value> Bucket<K, V> for ArrayBucket<K, V> {
             .find(|&&(k, _)| k == key)
             .map(|&(_, v)| v)
     }
-}
 
-pub type SmallVecBucketList<K, V> = SmallVec<[SmallVecBucket<K, V>; BUCKET_LIST_SIZE]>;
-pub type ArrayBucketList<K, V> = [ArrayBucket<K, V>; BUCKET_LIST_SIZE];
+    #[inline]
+    fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let len = self.len;
+        self.members[..len]
+            .iter_mut()
+            .find(|&&mut (k, _)| k == key)
+            .map(|&mut (_, ref mut v)| v)
+    }
 
-pub trait BucketList<K: Key, V: Value> {
-    type Bucket: Bucket<K, V>;
+    #[inline]
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut V)> {
+        let len = self.len;
+        self.members[..len]
+            .iter_mut()
+            .enumerate()
+            .find(|&(_, &mut (k, _))| k == key)
+            .map(|(idx, &mut (_, ref mut v))| (idx, v))
+    }
 
-    fn empty() -> Self;
-    fn len(&self) -> usize;
-    fn get(&self, idx: usize) -> &Self::Bucket;
-    fn get_mut(&mut self, idx: usize) -> &mut Self::Bucket;
+    #[inline]
+    fn take(&mut self, key: K) -> Option<(K, V)> {
+        let len = self.len;
+        self.members[..len]
+            .iter()
+            .position(|&(k, _)| k == key)
+            .map(|idx| self.remove_at(idx))
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> (K, V) {
+        let len = self.len;
+        assert!(index < len, "no entry at index {}", index);
+        self.members.swap(index, len - 1);
+        self.len -= 1;
+        self.members[len - 1]
+    }
+
+    #[inline]
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        let len = self.len;
+        let idx = match self.members[..len].iter().position(|&(k, _)| k == key) {
+            Some(idx) => idx,
+            None => {
+                self.push(key, f());
+                len
+            }
+        };
+        &mut self.members[idx].1
+    }
+
+    #[inline]
+    fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let len = self.len;
+        let mut kept = 0;
+        for idx in 0..len {
+            let (key, ref mut value) = self.members[idx];
+            if f(key, value) {
+                self.members.swap(kept, idx);
+                kept += 1;
+            }
+        }
+        self.len = kept;
+        len - kept
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        BUCKET_SIZE
+    }
+
+    #[inline]
+    fn reached_max_capacity(&self) -> bool {
+        self.len >= self.capacity()
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.members[..self.len].iter().cloned()
+    }
+
+    #[inline]
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut V)>
+    where
+        V: 'a,
+    {
+        self.members[..self.len]
+            .iter_mut()
+            .map(|&mut (k, ref mut v)| (k, v))
+    }
 }
 
-impl<K: Key, V: Value> BucketList<K, V> for SmallVecBucketList<K, V> {
-    type Bucket = SmallVecBucket<K, V>;
+/// Like `SmallVecBucket`, but `take` shifts the remaining entries instead of
+/// swapping in the last one, so iteration keeps insertion order.
+#[derive(Clone)]
+pub struct StableVecBucket<K: Key, V> {
+    members: SmallVec<[(K, V); BUCKET_SIZE]>,
+}
 
-    fn empty() -> Self {
-        let mut vec = SmallVec::new();
-        // pre-polulate vec
-        for _ in 0..BUCKET_LIST_SIZE {
-            vec.push(SmallVecBucket::new())
+impl<K: Key, V: Value> Bucket<K, V> for StableVecBucket<K, V> {
+    fn new() -> Self {
+        StableVecBucket {
+            members: SmallVec::new(),
         }
-        vec
     }
 
     #[inline]
-    fn len(&self) -> usize {
-        self.as_ref().len()
+    fn push(&mut self, key: K, value: V) {
+        self.members.push((key, value))
     }
 
     #[inline]
-    fn get(&self, idx: usize) -> &Self::Bucket {
-        &self[idx]
+    fn get(&self, key: K) -> Option<V> {
+        self.members
+            .iter()
+            .find(|&&(k, _)| k == key)
+            .map(|&(_, v)| v)
     }
 
     #[inline]
-    fn get_mut(&mut self, idx: usize) -> &mut Self::Bucket {
-        &mut self[idx]
+    fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        self.members
+            .iter_mut()
+            .find(|&&mut (k, _)| k == key)
+            .map(|&mut (_, ref mut v)| v)
+    }
+
+    #[inline]
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut V)> {
+        self.members
+            .iter_mut()
+            .enumerate()
+            .find(|&(_, &mut (k, _))| k == key)
+            .map(|(idx, &mut (_, ref mut v))| (idx, v))
+    }
+
+    #[inline]
+    fn take(&mut self, key: K) -> Option<(K, V)> {
+        self.members
+            .iter()
+            .position(|&(k, _)| k == key)
+            .map(|idx| self.remove_at(idx))
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> (K, V) {
+        self.members.remove(index)
+    }
+
+    #[inline]
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        let idx = match self.members.iter().position(|&(k, _)| k == key) {
+            Some(idx) => idx,
+            None => {
+                self.members.push((key, f()));
+                self.members.len() - 1
+            }
+        };
+        &mut self.members[idx].1
+    }
+
+    #[inline]
+    fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let len = self.members.len();
+        self.members.retain(|&mut (k, ref mut v)| f(k, v));
+        len - self.members.len()
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.members.clear()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.members.capacity()
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        self.members.reserve(additional)
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.members.shrink_to_fit()
+    }
+
+    #[inline]
+    fn reached_max_capacity(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.members.iter().cloned()
+    }
+
+    #[inline]
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut V)>
+    where
+        V: 'a,
+    {
+        self.members.iter_mut().map(|&mut (k, ref mut v)| (k, v))
+    }
+
+    #[inline]
+    fn extend_from<I: Iterator<Item = (K, V)>>(&mut self, entries: &mut I) {
+        self.members.extend(entries)
     }
 }
 
-impl<K: Key, V: Value> BucketList<K, V> for ArrayBucketList<K, V> {
-    type Bucket = ArrayBucket<K, V>;
+#[derive(Clone)]
+pub struct SortedVecBucket<K: Key, V> {
+    members: SmallVec<[(K, V); BUCKET_SIZE]>,
+}
 
-    fn empty() -> Self {
-        [ArrayBucket::new(); BUCKET_LIST_SIZE]
+impl<K: Key + Ord, V: Value> SortedVecBucket<K, V> {
+    #[inline]
+    fn position(&self, key: K) -> Result<usize, usize> {
+        self.members.binary_search_by_key(&key, |&(k, _)| k)
     }
 
     #[inline]
-    fn len(&self) -> usize {
-        self.as_ref().len()
+    pub fn binary_get(&self, key: K) -> Option<V> {
+        self.position(key).ok().map(|idx| self.members[idx].1)
+    }
+}
+
+impl<K: Key + Ord, V: Value> Bucket<K, V> for SortedVecBucket<K, V> {
+    fn new() -> Self {
+        SortedVecBucket {
+            members: SmallVec::new(),
+        }
     }
 
     #[inline]
-    fn get(&self, idx: usize) -> &Self::Bucket {
-        &self[idx]
+    fn push(&mut self, key: K, value: V) {
+        let idx = match self.position(key) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        self.members.insert(idx, (key, value))
     }
 
     #[inline]
-    fn get_mut(&mut self, idx: usize) -> &mut Self::Bucket {
-        &mut self[idx]
+    fn get(&self, key: K) -> Option<V> {
+        self.binary_get(key)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        match self.position(key) {
+            Ok(idx) => Some(&mut self.members[idx].1),
+            Err(_) => None,
+        }
+    }
+
+    #[inline]
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut V)> {
+        match self.position(key) {
+            Ok(idx) => Some((idx, &mut self.members[idx].1)),
+            Err(_) => None,
+        }
+    }
+
+    #[inline]
+    fn take(&mut self, key: K) -> Option<(K, V)> {
+        self.position(key).ok().map(|idx| self.remove_at(idx))
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> (K, V) {
+        self.members.remove(index)
+    }
+
+    #[inline]
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        let idx = match self.position(key) {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.members.insert(idx, (key, f()));
+                idx
+            }
+        };
+        &mut self.members[idx].1
+    }
+
+    #[inline]
+    fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let len = self.members.len();
+        self.members.retain(|&mut (k, ref mut v)| f(k, v));
+        len - self.members.len()
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.members.clear()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.members.capacity()
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        self.members.reserve(additional)
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.members.shrink_to_fit()
+    }
+
+    #[inline]
+    fn reached_max_capacity(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.members.iter().cloned()
+    }
+
+    #[inline]
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut V)>
+    where
+        V: 'a,
+    {
+        self.members.iter_mut().map(|&mut (k, ref mut v)| (k, v))
+    }
+}
+
+/// Keeps its entries in a `BTreeMap`, so a bucket that collects a huge
+/// number of keys under a skewed or adversarial hash is still searched in
+/// O(log n). Costs an allocation per non-empty bucket and is slower than a
+/// scan for the usual handful of entries. Positions are in key order and
+/// take a linear walk to resolve, so the map doesn't ask for them on its
+/// lookup and removal paths. Like the other buckets, pushing a key
+/// twice keeps both entries and lookups see the first one.
+#[derive(Clone)]
+pub struct BTreeBucket<K: Key, V> {
+    members: BTreeMap<K, SmallVec<[V; 1]>>,
+}
+
+impl<K: Key + Ord, V: Value> BTreeBucket<K, V> {
+    #[inline]
+    fn position(&self, key: K) -> usize {
+        self.members
+            .range(..key)
+            .map(|(_, values)| values.len())
+            .sum()
+    }
+
+    #[inline]
+    fn remove_value(&mut self, key: K, idx: usize) -> (K, V) {
+        let values = self.members.get_mut(&key).unwrap();
+        let value = values.remove(idx);
+        if values.is_empty() {
+            self.members.remove(&key);
+        }
+        (key, value)
+    }
+}
+
+impl<K: Key + Ord, V: Value> Bucket<K, V> for BTreeBucket<K, V> {
+    const CHEAP_POSITIONS: bool = false;
+
+    fn new() -> Self {
+        BTreeBucket {
+            members: BTreeMap::new(),
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, key: K, value: V) {
+        self.members.entry(key).or_default().push(value)
+    }
+
+    #[inline]
+    fn get(&self, key: K) -> Option<V> {
+        self.members.get(&key).map(|values| values[0])
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        self.members.get_mut(&key).map(|values| &mut values[0])
+    }
+
+    #[inline]
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut V)> {
+        let idx = self.position(key);
+        self.get_mut(key).map(|v| (idx, v))
+    }
+
+    #[inline]
+    fn take(&mut self, key: K) -> Option<(K, V)> {
+        if !self.members.contains_key(&key) {
+            return None;
+        }
+        Some(self.remove_value(key, 0))
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> (K, V) {
+        let mut skipped = 0;
+        for (&key, values) in &self.members {
+            if index < skipped + values.len() {
+                return self.remove_value(key, index - skipped);
+            }
+            skipped += values.len();
+        }
+        panic!("no entry at index {}", index)
+    }
+
+    #[inline]
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        &mut self
+            .members
+            .entry(key)
+            .or_insert_with(|| SmallVec::from_elem(f(), 1))[0]
+    }
+
+    #[inline]
+    fn contains(&self, key: K) -> bool {
+        self.members.contains_key(&key)
+    }
+
+    #[inline]
+    fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let mut removed = 0;
+        self.members.retain(|&k, values| {
+            let len = values.len();
+            values.retain(|v| f(k, v));
+            removed += len - values.len();
+            !values.is_empty()
+        });
+        removed
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.members.clear()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.members.values().map(|values| values.len()).sum()
+    }
+
+    #[inline]
+    fn reached_max_capacity(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.members
+            .iter()
+            .flat_map(|(&k, values)| values.iter().map(move |&v| (k, v)))
+    }
+
+    #[inline]
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut V)>
+    where
+        V: 'a,
+    {
+        self.members
+            .iter_mut()
+            .flat_map(|(&k, values)| values.iter_mut().map(move |v| (k, v)))
+    }
+}
+
+/// Stores keys and values in separate arrays, so that lookups only scan
+/// the keys.
+#[derive(Clone)]
+pub struct SoaBucket<K: Key, V> {
+    keys: SmallVec<[K; BUCKET_SIZE]>,
+    values: SmallVec<[V; BUCKET_SIZE]>,
+}
+
+impl<K: Key, V: Value> SoaBucket<K, V> {
+    #[inline]
+    fn position(&self, key: K) -> Option<usize> {
+        self.keys.iter().position(|&k| k == key)
+    }
+}
+
+impl<K: Key, V: Value> Bucket<K, V> for SoaBucket<K, V> {
+    fn new() -> Self {
+        SoaBucket {
+            keys: SmallVec::new(),
+            values: SmallVec::new(),
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, key: K, value: V) {
+        self.keys.push(key);
+        self.values.push(value);
+    }
+
+    #[inline]
+    fn get(&self, key: K) -> Option<V> {
+        self.position(key).map(|idx| self.values[idx])
+    }
+
+    #[inline]
+    fn contains(&self, key: K) -> bool {
+        self.position(key).is_some()
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        self.find_mut(key).map(|(_, v)| v)
+    }
+
+    #[inline]
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut V)> {
+        match self.position(key) {
+            Some(idx) => Some((idx, &mut self.values[idx])),
+            None => None,
+        }
+    }
+
+    #[inline]
+    fn take(&mut self, key: K) -> Option<(K, V)> {
+        self.position(key).map(|idx| self.remove_at(idx))
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> (K, V) {
+        (self.keys.swap_remove(index), self.values.swap_remove(index))
+    }
+
+    #[inline]
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        let idx = match self.position(key) {
+            Some(idx) => idx,
+            None => {
+                self.push(key, f());
+                self.keys.len() - 1
+            }
+        };
+        &mut self.values[idx]
+    }
+
+    #[inline]
+    fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let len = self.keys.len();
+        let mut kept = 0;
+        for idx in 0..len {
+            if f(self.keys[idx], &mut self.values[idx]) {
+                self.keys.swap(kept, idx);
+                self.values.swap(kept, idx);
+                kept += 1;
+            }
+        }
+        self.keys.truncate(kept);
+        self.values.truncate(kept);
+        len - kept
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.values.clear();
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.keys.capacity().min(self.values.capacity())
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        self.keys.reserve(additional);
+        self.values.reserve(additional);
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.keys.shrink_to_fit();
+        self.values.shrink_to_fit();
+    }
+
+    #[inline]
+    fn reached_max_capacity(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.keys.iter().cloned().zip(self.values.iter().cloned())
+    }
+
+    #[inline]
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut V)>
+    where
+        V: 'a,
+    {
+        self.keys.iter().cloned().zip(self.values.iter_mut())
+    }
+}
+
+/// A bucket for sets, storing only the keys.
+#[derive(Clone)]
+pub struct KeysOnlyBucket<K: Key> {
+    keys: SmallVec<[K; BUCKET_SIZE]>,
+}
+
+impl<K: Key> KeysOnlyBucket<K> {
+    // `()` takes no memory, so leaking a box of it allocates nothing.
+    #[inline]
+    fn unit<'a>() -> &'a mut () {
+        Box::leak(Box::new(()))
+    }
+}
+
+impl<K: Key> Bucket<K, ()> for KeysOnlyBucket<K> {
+    fn new() -> Self {
+        KeysOnlyBucket {
+            keys: SmallVec::new(),
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, key: K, _value: ()) {
+        self.keys.push(key)
+    }
+
+    #[inline]
+    fn get(&self, key: K) -> Option<()> {
+        self.keys.iter().find(|&&k| k == key).map(|_| ())
+    }
+
+    #[inline]
+    fn contains(&self, key: K) -> bool {
+        self.keys.contains(&key)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: K) -> Option<&mut ()> {
+        self.get(key).map(|_| Self::unit())
+    }
+
+    #[inline]
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut ())> {
+        self.keys
+            .iter()
+            .position(|&k| k == key)
+            .map(|idx| (idx, Self::unit()))
+    }
+
+    #[inline]
+    fn take(&mut self, key: K) -> Option<(K, ())> {
+        self.keys
+            .iter()
+            .position(|&k| k == key)
+            .map(|idx| self.remove_at(idx))
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> (K, ()) {
+        (self.keys.swap_remove(index), ())
+    }
+
+    #[inline]
+    fn get_or_insert_with<F: FnOnce()>(&mut self, key: K, f: F) -> &mut () {
+        if self.get(key).is_none() {
+            f();
+            self.keys.push(key);
+        }
+        Self::unit()
+    }
+
+    #[inline]
+    fn retain<F: FnMut(K, &mut ()) -> bool>(&mut self, mut f: F) -> usize {
+        let len = self.keys.len();
+        self.keys.retain(|&mut k| f(k, &mut ()));
+        len - self.keys.len()
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.keys.clear()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.keys.capacity()
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        self.keys.reserve(additional)
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.keys.shrink_to_fit()
+    }
+
+    #[inline]
+    fn reached_max_capacity(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (K, ())> {
+        self.keys.iter().map(|&k| (k, ()))
+    }
+
+    #[inline]
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut ())>
+    where
+        (): 'a,
+    {
+        self.keys.iter().map(|&k| (k, Self::unit()))
+    }
+
+    #[inline]
+    fn extend_from<I: Iterator<Item = (K, ())>>(&mut self, entries: &mut I) {
+        self.keys.extend(entries.map(|(k, _)| k))
+    }
+}
+
+thread_local! {
+    static KEY_COMPARISONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A key that counts every comparison made against it on the current
+/// thread. `CountingBucket` stores its keys as these, so it sees exactly
+/// the comparisons the inner bucket makes, whatever its search strategy.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CountedKey<K>(pub K);
+
+impl<K: Hash> Hash for CountedKey<K> {}
+
+impl<K: PartialEq> PartialEq for CountedKey<K> {
+    fn eq(&self, other: &Self) -> bool {
+        KEY_COMPARISONS.with(|count| count.set(count.get() + 1));
+        self.0 == other.0
+    }
+}
+
+impl<K: Eq> Eq for CountedKey<K> {}
+
+impl<K: Ord> PartialOrd for CountedKey<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord> Ord for CountedKey<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        KEY_COMPARISONS.with(|count| count.set(count.get() + 1));
+        self.0.cmp(&other.0)
+    }
+}
+
+fn key_comparisons() -> u64 {
+    KEY_COMPARISONS.with(|count| count.get())
+}
+
+// Adds the comparisons made on this thread since `start` was read.
+fn add_comparisons(counter: &Cell<u64>, start: u64) {
+    counter.set(counter.get() + key_comparisons() - start)
+}
+
+/// Wraps a bucket keyed by `CountedKey` and counts the key comparisons
+/// made by its lookups and inserts.
+#[derive(Clone)]
+pub struct CountingBucket<B> {
+    inner: B,
+    comparisons: Cell<u64>,
+}
+
+impl<B> CountingBucket<B> {
+    pub fn comparisons(&self) -> u64 {
+        self.comparisons.get()
+    }
+
+    pub fn reset_comparisons(&self) {
+        self.comparisons.set(0)
+    }
+}
+
+impl<K: Key, V: Value, B: Bucket<CountedKey<K>, V>> Bucket<K, V> for CountingBucket<B> {
+    const CHEAP_POSITIONS: bool = B::CHEAP_POSITIONS;
+
+    fn new() -> Self {
+        CountingBucket {
+            inner: B::new(),
+            comparisons: Cell::new(0),
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, key: K, value: V) {
+        let start = key_comparisons();
+        self.inner.push(CountedKey(key), value);
+        add_comparisons(&self.comparisons, start)
+    }
+
+    #[inline]
+    fn get(&self, key: K) -> Option<V> {
+        let start = key_comparisons();
+        let value = self.inner.get(CountedKey(key));
+        add_comparisons(&self.comparisons, start);
+        value
+    }
+
+    #[inline]
+    fn contains(&self, key: K) -> bool {
+        let start = key_comparisons();
+        let found = self.inner.contains(CountedKey(key));
+        add_comparisons(&self.comparisons, start);
+        found
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let start = key_comparisons();
+        let value = self.inner.get_mut(CountedKey(key));
+        add_comparisons(&self.comparisons, start);
+        value
+    }
+
+    #[inline]
+    fn find_mut(&mut self, key: K) -> Option<(usize, &mut V)> {
+        let start = key_comparisons();
+        let found = self.inner.find_mut(CountedKey(key));
+        add_comparisons(&self.comparisons, start);
+        found
+    }
+
+    #[inline]
+    fn take(&mut self, key: K) -> Option<(K, V)> {
+        let start = key_comparisons();
+        let entry = self.inner.take(CountedKey(key));
+        add_comparisons(&self.comparisons, start);
+        entry.map(|(k, v)| (k.0, v))
+    }
+
+    #[inline]
+    fn remove_at(&mut self, index: usize) -> (K, V) {
+        let (k, v) = self.inner.remove_at(index);
+        (k.0, v)
+    }
+
+    #[inline]
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        let start = key_comparisons();
+        let value = self.inner.get_or_insert_with(CountedKey(key), f);
+        add_comparisons(&self.comparisons, start);
+        value
+    }
+
+    #[inline]
+    fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        self.inner.retain(|k, v| f(k.0, v))
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        self.inner.reserve(additional)
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit()
+    }
+
+    #[inline]
+    fn reached_max_capacity(&self) -> bool {
+        self.inner.reached_max_capacity()
+    }
+
+    #[inline]
+    fn iter(&self) -> impl Iterator<Item = (K, V)> {
+        self.inner.iter().map(|(k, v)| (k.0, v))
+    }
+
+    #[inline]
+    fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (K, &'a mut V)>
+    where
+        V: 'a,
+    {
+        self.inner.iter_mut().map(|(k, v)| (k.0, v))
+    }
+
+    #[inline]
+    fn extend_from<I: Iterator<Item = (K, V)>>(&mut self, entries: &mut I) {
+        let mut entries = entries.map(|(k, v)| (CountedKey(k), v));
+        self.inner.extend_from(&mut entries)
+    }
+}
+
+pub type SmallVecBucketList<K, V> = SmallVec<[SmallVecBucket<K, V>; BUCKET_LIST_SIZE]>;
+pub type ArrayBucketList<K, V> = [ArrayBucket<K, V>; BUCKET_LIST_SIZE];
+pub type KeysOnlyBucketList<K> = SmallVec<[KeysOnlyBucket<K>; BUCKET_LIST_SIZE]>;
+
+pub trait BucketList<K: Key, V: Value> {
+    type Bucket: Bucket<K, V>;
+
+    fn empty() -> Self;
+    fn len(&self) -> usize;
+    fn get(&self, idx: usize) -> &Self::Bucket;
+    fn get_mut(&mut self, idx: usize) -> &mut Self::Bucket;
+    fn buckets_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut Self::Bucket>
+    where
+        Self::Bucket: 'a;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Key, V: Value, B: Bucket<K, V>> BucketList<K, V> for SmallVec<[B; BUCKET_LIST_SIZE]> {
+    type Bucket = B;
+
+    fn empty() -> Self {
+        let mut vec = SmallVec::new();
+        // pre-polulate vec
+        for _ in 0..BUCKET_LIST_SIZE {
+            vec.push(B::new())
+        }
+        vec
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    #[inline]
+    fn get(&self, idx: usize) -> &Self::Bucket {
+        &self[idx]
+    }
+
+    #[inline]
+    fn get_mut(&mut self, idx: usize) -> &mut Self::Bucket {
+        &mut self[idx]
+    }
+
+    #[inline]
+    fn buckets_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut Self::Bucket>
+    where
+        Self::Bucket: 'a,
+    {
+        self.iter_mut()
+    }
+}
+
+pub trait GrowableBucketList<K: Key, V: Value>: BucketList<K, V> {
+    fn with_len(len: usize) -> Self;
+}
+
+impl<K: Key, V: Value, B: Bucket<K, V>> GrowableBucketList<K, V>
+    for SmallVec<[B; BUCKET_LIST_SIZE]>
+{
+    fn with_len(len: usize) -> Self {
+        let mut vec = SmallVec::with_capacity(len);
+        for _ in 0..len {
+            vec.push(B::new())
+        }
+        vec
+    }
+}
+
+impl<K: Key, V: Value, B: Bucket<K, V>, const N: usize> BucketList<K, V> for [B; N] {
+    type Bucket = B;
+
+    fn empty() -> Self {
+        array::from_fn(|_| B::new())
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        N
+    }
+
+    #[inline]
+    fn get(&self, idx: usize) -> &Self::Bucket {
+        &self[idx]
+    }
+
+    #[inline]
+    fn get_mut(&mut self, idx: usize) -> &mut Self::Bucket {
+        &mut self[idx]
+    }
+
+    #[inline]
+    fn buckets_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut Self::Bucket>
+    where
+        Self::Bucket: 'a,
+    {
+        self.iter_mut()
     }
 }
@@ -0,0 +1,112 @@
+use std::vec;
+
+use {Bucket, BucketList, Hasher, Key, PrimitiveMap, Value};
+
+/// Removes and yields the entries matching a predicate. Dropping it before
+/// the end still removes every remaining match.
+pub struct DrainFilter<'a, K, V, B, BL, H, F>
+where
+    K: Key + 'a,
+    V: Value + 'a,
+    B: Bucket<K, V> + 'a,
+    BL: BucketList<K, V, Bucket = B> + 'a,
+    H: Hasher<K> + 'a,
+    F: FnMut(K, &mut V) -> bool,
+{
+    map: &'a mut PrimitiveMap<K, V, B, BL, H>,
+    pred: F,
+    next_bucket: usize,
+    drained: vec::IntoIter<(K, V)>,
+}
+
+impl<K, V, B, BL, H> PrimitiveMap<K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    pub fn drain_filter<F>(&mut self, pred: F) -> DrainFilter<'_, K, V, B, BL, H, F>
+    where
+        F: FnMut(K, &mut V) -> bool,
+    {
+        DrainFilter {
+            map: self,
+            pred,
+            next_bucket: 0,
+            drained: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<'a, K, V, B, BL, H, F> Iterator for DrainFilter<'a, K, V, B, BL, H, F>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+    F: FnMut(K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            if let Some(entry) = self.drained.next() {
+                return Some(entry);
+            }
+            if self.next_bucket == self.map.buckets.len() {
+                return None;
+            }
+
+            // Each bucket is drained as a whole, the iterator then hands out
+            // its entries one by one. Once a full bucket loses entries, the
+            // full buckets after it are drained too and the entries that
+            // spilled into them are placed again, so the map stays valid
+            // even if the iterator is leaked.
+            let len = self.map.buckets.len();
+            let start = self.next_bucket;
+            let mut end = start;
+            let mut drained = Vec::new();
+            let mut spilled = false;
+            loop {
+                let bucket = self.map.buckets.get_mut(end % len);
+                let full = bucket.reached_max_capacity();
+                // Buckets past the end wrapped around and were already
+                // drained, they only take part in placing entries again.
+                if end < len {
+                    let before = drained.len();
+                    drained.extend(bucket.drain_filter(&mut self.pred));
+                    spilled |= full && drained.len() > before;
+                }
+                end += 1;
+                if !spilled || !full || end - start == len {
+                    break;
+                }
+            }
+
+            self.map.len -= drained.len();
+            self.next_bucket = end.min(len);
+            if spilled {
+                self.map.replace_spilled_in(start, end - start);
+            }
+            self.drained = drained.into_iter();
+        }
+    }
+}
+
+impl<'a, K, V, B, BL, H, F> Drop for DrainFilter<'a, K, V, B, BL, H, F>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+    F: FnMut(K, &mut V) -> bool,
+{
+    fn drop(&mut self) {
+        for _ in self.by_ref() {}
+        self.map.maybe_shrink();
+    }
+}
@@ -0,0 +1,217 @@
+use std::mem;
+
+use {Bucket, BucketList, CapacityError, Hasher, Key, PrimitiveMap, Value};
+
+pub enum Entry<'a, K, V, B, BL, H>
+where
+    K: Key + 'a,
+    V: Value + 'a,
+    B: Bucket<K, V> + 'a,
+    BL: BucketList<K, V, Bucket = B> + 'a,
+    H: Hasher<K> + 'a,
+{
+    Occupied(OccupiedEntry<'a, K, V, B, BL, H>),
+    Vacant(VacantEntry<'a, K, V, B, BL, H>),
+}
+
+pub struct OccupiedEntry<'a, K, V, B, BL, H>
+where
+    K: Key + 'a,
+    V: Value + 'a,
+    B: Bucket<K, V> + 'a,
+    BL: BucketList<K, V, Bucket = B> + 'a,
+    H: Hasher<K> + 'a,
+{
+    map: &'a mut PrimitiveMap<K, V, B, BL, H>,
+    key: K,
+    addr: usize,
+    // Position within the bucket at `addr`, so removal doesn't search
+    // again. `None` for buckets without cheap positions.
+    index: Option<usize>,
+}
+
+pub struct VacantEntry<'a, K, V, B, BL, H>
+where
+    K: Key + 'a,
+    V: Value + 'a,
+    B: Bucket<K, V> + 'a,
+    BL: BucketList<K, V, Bucket = B> + 'a,
+    H: Hasher<K> + 'a,
+{
+    map: &'a mut PrimitiveMap<K, V, B, BL, H>,
+    key: K,
+    addr: usize,
+}
+
+impl<K, V, B, BL, H> PrimitiveMap<K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, B, BL, H> {
+        let addr = self.get_addr(key);
+        match self.find_slot(addr, key) {
+            Some((addr, index)) => Entry::Occupied(OccupiedEntry {
+                map: self,
+                key,
+                addr,
+                index,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                map: self,
+                key,
+                addr,
+            }),
+        }
+    }
+
+    /// Like `entry`, but fails instead of returning a vacant entry that
+    /// has nowhere to go. The entry keeps the map borrowed, so inserting
+    /// into the returned vacant entry can't run out of room.
+    pub fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V, B, BL, H>, CapacityError<K>> {
+        let addr = self.get_addr(key);
+        if self.find_addr(addr, key).is_none() && self.vacant_addr(addr).is_none() {
+            return Err(CapacityError {
+                key,
+                probes: self.max_probes(),
+            });
+        }
+        Ok(self.entry(key))
+    }
+}
+
+impl<'a, K, V, B, BL, H> Entry<'a, K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    pub fn key(&self) -> K {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    /// The bucket holding an occupied entry, or the home bucket of a
+    /// vacant one. These differ from `bucket_index` for entries that
+    /// spilled out of a full bucket.
+    pub fn bucket_index(&self) -> usize {
+        match *self {
+            Entry::Occupied(ref entry) => entry.addr,
+            Entry::Vacant(ref entry) => entry.addr,
+        }
+    }
+
+    pub fn is_occupied(&self) -> bool {
+        match *self {
+            Entry::Occupied(_) => true,
+            Entry::Vacant(_) => false,
+        }
+    }
+
+    pub fn get(&self) -> Option<V> {
+        match *self {
+            Entry::Occupied(ref entry) => Some(entry.get()),
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_try_insert_with<E, F: FnOnce() -> Result<V, E>>(
+        self,
+        default: F,
+    ) -> Result<&'a mut V, E> {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => default().map(|value| entry.insert(value)),
+        }
+    }
+}
+
+impl<'a, K, V, B, BL, H> OccupiedEntry<'a, K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    pub fn key(&self) -> K {
+        self.key
+    }
+
+    pub fn get(&self) -> V {
+        self.map.buckets.get(self.addr).get(self.key).unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.map
+            .buckets
+            .get_mut(self.addr)
+            .get_mut(self.key)
+            .unwrap()
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.map
+            .buckets
+            .get_mut(self.addr)
+            .get_mut(self.key)
+            .unwrap()
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        match self.index {
+            Some(index) => self.map.remove_slot(self.addr, index),
+            None => self.map.take_at(self.addr, self.key).unwrap(),
+        }
+    }
+}
+
+impl<'a, K, V, B, BL, H> VacantEntry<'a, K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    pub fn key(&self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        let addr = match self.map.push_vacant(self.addr, self.key, value) {
+            Ok(addr) => addr,
+            Err(err) => panic!("{}", err),
+        };
+        self.map.buckets.get_mut(addr).get_mut(self.key).unwrap()
+    }
+}
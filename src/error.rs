@@ -0,0 +1,44 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeError<K, V> {
+    pub key: K,
+    pub value: V,
+    pub probes: usize,
+}
+
+impl<K, V> fmt::Display for ProbeError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no bucket with room within {} probes", self.probes)
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> Error for ProbeError<K, V> {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CapacityError<K> {
+    pub key: K,
+    pub probes: usize,
+}
+
+impl<K> fmt::Display for CapacityError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no bucket with room within {} probes", self.probes)
+    }
+}
+
+impl<K: fmt::Debug> Error for CapacityError<K> {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverlapError<K> {
+    pub key: K,
+}
+
+impl<K: fmt::Debug> fmt::Display for OverlapError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "key {:?} is requested more than once", self.key)
+    }
+}
+
+impl<K: fmt::Debug> Error for OverlapError<K> {}
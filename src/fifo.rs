@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+
+use bucket::{SmallVecBucket, SmallVecBucketList};
+use entry::Entry;
+use hash::{DefaultHasher, Hasher};
+use kv::{Key, Value};
+use PrimitiveMap;
+
+type InnerMap<K, V> =
+    PrimitiveMap<K, V, SmallVecBucket<K, V>, SmallVecBucketList<K, V>, DefaultHasher<K>>;
+
+/// A map holding at most `limit` entries. Inserting a new key into a full
+/// map evicts the key that was inserted first, updates don't change the
+/// order.
+pub struct FifoPrimitiveMap<K: Key, V: Value>
+where
+    DefaultHasher<K>: Hasher<K>,
+{
+    map: InnerMap<K, V>,
+    order: VecDeque<K>,
+    limit: usize,
+}
+
+impl<K: Key, V: Value> FifoPrimitiveMap<K, V>
+where
+    DefaultHasher<K>: Hasher<K>,
+{
+    pub fn with_limit(limit: usize) -> Self {
+        assert!(limit > 0, "limit must be greater than 0");
+        FifoPrimitiveMap {
+            map: PrimitiveMap::dynamic(),
+            order: VecDeque::with_capacity(limit),
+            limit,
+        }
+    }
+
+    /// Returns the evicted entry, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        match self.map.entry(key) {
+            Entry::Occupied(mut entry) => {
+                entry.insert(value);
+                return None;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+
+        self.order.push_back(key);
+        if self.order.len() > self.limit {
+            let oldest = self.order.pop_front().unwrap();
+            self.map.take(oldest)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        self.map.get(key)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
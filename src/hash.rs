@@ -1,5 +1,6 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher as StdHasher};
 use std::marker::PhantomData;
-use std::{u16, u8};
 
 pub trait Hash: Sized + Copy {}
 
@@ -13,93 +14,398 @@ impl Hash for u64 {}
 impl Hash for usize {}
 
 pub trait Hasher<T: Hash> {
-    fn hash(value: T) -> T;
+    fn hash(&self, value: T) -> T;
     fn compress(hash: T, upper_bound: usize) -> usize;
+
+    fn hash_many(&self, values: &[T]) -> Vec<T> {
+        values.iter().map(|&value| self.hash(value)).collect()
+    }
 }
 
-#[derive(Default)]
-pub struct DefaultHasher<T: Hash> {
-    _marker: PhantomData<T>,
+/// Maps a hash onto a bucket address below `upper_bound`.
+pub trait Compress {
+    fn compress(hash: u64, upper_bound: usize) -> usize;
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Modulo;
+
+impl Compress for Modulo {
+    fn compress(hash: u64, upper_bound: usize) -> usize {
+        (hash as usize) % upper_bound
+    }
+}
+
+/// Keeps the low bits of the hash when `upper_bound` is a power of two,
+/// and falls back to modulo for the other bucket counts a map can pick.
+#[derive(Default, Clone, Copy)]
+pub struct Mask;
+
+impl Compress for Mask {
+    fn compress(hash: u64, upper_bound: usize) -> usize {
+        if upper_bound.is_power_of_two() {
+            (hash as usize) & (upper_bound - 1)
+        } else {
+            (hash as usize) % upper_bound
+        }
+    }
+}
+
+/// Multiplies by 2^64 / phi and keeps the high bits, which spreads keys
+/// that only differ in their high bits.
+#[derive(Default, Clone, Copy)]
+pub struct Fibonacci;
+
+impl Compress for Fibonacci {
+    fn compress(hash: u64, upper_bound: usize) -> usize {
+        let mixed = hash.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        ((u128::from(mixed) * upper_bound as u128) >> 64) as usize
+    }
 }
 
-impl Hasher<u8> for DefaultHasher<u8> {
-    fn hash(value: u8) -> u8 {
+/// Cheap bit mixing followed by the compression strategy `C`.
+#[derive(Default, Clone, Copy)]
+pub struct FastHasher<T: Hash, C: Compress> {
+    _marker: PhantomData<(T, C)>,
+}
+
+pub type DefaultHasher<T> = FastHasher<T, Modulo>;
+
+impl<C: Compress> Hasher<u8> for FastHasher<u8, C> {
+    fn hash(&self, value: u8) -> u8 {
         value
     }
 
     fn compress(hash: u8, upper_bound: usize) -> usize {
-        debug_assert!(upper_bound >= u8::MAX as usize);
-        hash as usize
+        C::compress(u64::from(hash), upper_bound)
     }
 }
 
-impl Hasher<i8> for DefaultHasher<i8> {
-    fn hash(value: i8) -> i8 {
+impl<C: Compress> Hasher<i8> for FastHasher<i8, C> {
+    fn hash(&self, value: i8) -> i8 {
         value
     }
 
     fn compress(hash: i8, upper_bound: usize) -> usize {
-        debug_assert!(upper_bound >= u8::MAX as usize);
-        hash as usize
+        C::compress(hash as u64, upper_bound)
     }
 }
 
-impl Hasher<u16> for DefaultHasher<u16> {
-    fn hash(value: u16) -> u16 {
+impl<C: Compress> Hasher<u16> for FastHasher<u16, C> {
+    fn hash(&self, value: u16) -> u16 {
         value ^ value >> 7
     }
 
     fn compress(hash: u16, upper_bound: usize) -> usize {
-        (hash as usize) % upper_bound
+        C::compress(u64::from(hash), upper_bound)
     }
 }
 
-impl Hasher<i16> for DefaultHasher<i16> {
-    fn hash(value: i16) -> i16 {
+impl<C: Compress> Hasher<i16> for FastHasher<i16, C> {
+    fn hash(&self, value: i16) -> i16 {
         value ^ value >> 7
     }
 
     fn compress(hash: i16, upper_bound: usize) -> usize {
-        (hash as usize) % upper_bound
+        C::compress(hash as u64, upper_bound)
     }
 }
 
-impl Hasher<u32> for DefaultHasher<u32> {
-    fn hash(value: u32) -> u32 {
+impl<C: Compress> Hasher<u32> for FastHasher<u32, C> {
+    fn hash(&self, value: u32) -> u32 {
         value ^ value >> 7
     }
 
     fn compress(hash: u32, upper_bound: usize) -> usize {
-        (hash as usize) % upper_bound
+        C::compress(u64::from(hash), upper_bound)
     }
 }
 
-impl Hasher<i32> for DefaultHasher<i32> {
-    fn hash(value: i32) -> i32 {
+impl<C: Compress> Hasher<i32> for FastHasher<i32, C> {
+    fn hash(&self, value: i32) -> i32 {
         value ^ value >> 7
     }
 
     fn compress(hash: i32, upper_bound: usize) -> usize {
-        (hash as usize) % upper_bound
+        C::compress(hash as u64, upper_bound)
     }
 }
 
-impl Hasher<u64> for DefaultHasher<u64> {
-    fn hash(value: u64) -> u64 {
+impl<C: Compress> Hasher<u64> for FastHasher<u64, C> {
+    fn hash(&self, value: u64) -> u64 {
         value ^ value >> 7
     }
 
     fn compress(hash: u64, upper_bound: usize) -> usize {
-        (hash as usize) % upper_bound
+        C::compress(hash, upper_bound)
     }
 }
 
-impl Hasher<usize> for DefaultHasher<usize> {
-    fn hash(value: usize) -> usize {
+impl<C: Compress> Hasher<usize> for FastHasher<usize, C> {
+    fn hash(&self, value: usize) -> usize {
         value ^ value >> 7
     }
 
     fn compress(hash: usize, upper_bound: usize) -> usize {
+        C::compress(hash as u64, upper_bound)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SipHasher<T: Hash> {
+    k0: u64,
+    k1: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Hash> SipHasher<T> {
+    pub fn new() -> Self {
+        let mut seed = RandomState::new().build_hasher();
+        seed.write_u64(0);
+        let k0 = seed.finish();
+        seed.write_u64(1);
+        let k1 = seed.finish();
+        SipHasher::with_keys(k0, k1)
+    }
+
+    pub fn with_keys(k0: u64, k1: u64) -> Self {
+        SipHasher {
+            k0,
+            k1,
+            _marker: PhantomData,
+        }
+    }
+
+    // SipHash-1-3 of the little-endian bytes of `message`
+    fn sip13(&self, message: u64) -> u64 {
+        let mut v = [
+            self.k0 ^ 0x736f_6d65_7073_6575,
+            self.k1 ^ 0x646f_7261_6e64_6f6d,
+            self.k0 ^ 0x6c79_6765_6e65_7261,
+            self.k1 ^ 0x7465_6462_7974_6573,
+        ];
+
+        v[3] ^= message;
+        sip_round(&mut v);
+        v[0] ^= message;
+
+        let length = 8u64 << 56;
+        v[3] ^= length;
+        sip_round(&mut v);
+        v[0] ^= length;
+
+        v[2] ^= 0xff;
+        for _ in 0..3 {
+            sip_round(&mut v);
+        }
+        v[0] ^ v[1] ^ v[2] ^ v[3]
+    }
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+impl<T: Hash> Default for SipHasher<T> {
+    fn default() -> Self {
+        SipHasher::new()
+    }
+}
+
+impl Hasher<u8> for SipHasher<u8> {
+    fn hash(&self, value: u8) -> u8 {
+        self.sip13(u64::from(value)) as u8
+    }
+
+    fn compress(hash: u8, upper_bound: usize) -> usize {
+        (hash as usize) % upper_bound
+    }
+}
+
+impl Hasher<i8> for SipHasher<i8> {
+    fn hash(&self, value: i8) -> i8 {
+        self.sip13(value as u64) as i8
+    }
+
+    fn compress(hash: i8, upper_bound: usize) -> usize {
+        (hash as usize) % upper_bound
+    }
+}
+
+impl Hasher<u16> for SipHasher<u16> {
+    fn hash(&self, value: u16) -> u16 {
+        self.sip13(u64::from(value)) as u16
+    }
+
+    fn compress(hash: u16, upper_bound: usize) -> usize {
+        (hash as usize) % upper_bound
+    }
+}
+
+impl Hasher<i16> for SipHasher<i16> {
+    fn hash(&self, value: i16) -> i16 {
+        self.sip13(value as u64) as i16
+    }
+
+    fn compress(hash: i16, upper_bound: usize) -> usize {
+        (hash as usize) % upper_bound
+    }
+}
+
+impl Hasher<u32> for SipHasher<u32> {
+    fn hash(&self, value: u32) -> u32 {
+        self.sip13(u64::from(value)) as u32
+    }
+
+    fn compress(hash: u32, upper_bound: usize) -> usize {
+        (hash as usize) % upper_bound
+    }
+}
+
+impl Hasher<i32> for SipHasher<i32> {
+    fn hash(&self, value: i32) -> i32 {
+        self.sip13(value as u64) as i32
+    }
+
+    fn compress(hash: i32, upper_bound: usize) -> usize {
+        (hash as usize) % upper_bound
+    }
+}
+
+impl Hasher<u64> for SipHasher<u64> {
+    fn hash(&self, value: u64) -> u64 {
+        self.sip13(value)
+    }
+
+    fn compress(hash: u64, upper_bound: usize) -> usize {
         (hash as usize) % upper_bound
     }
 }
+
+impl Hasher<usize> for SipHasher<usize> {
+    fn hash(&self, value: usize) -> usize {
+        self.sip13(value as u64) as usize
+    }
+
+    fn compress(hash: usize, upper_bound: usize) -> usize {
+        hash % upper_bound
+    }
+}
+
+/// Runs the output of `H` through the MurmurHash3 finalizer, so that every
+/// bit of the key affects the low bits used by the compression.
+#[derive(Default, Clone, Copy)]
+pub struct Finalized<H> {
+    hasher: H,
+}
+
+impl<H> Finalized<H> {
+    pub fn new(hasher: H) -> Self {
+        Finalized { hasher }
+    }
+}
+
+fn fmix32(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ h >> 16
+}
+
+fn fmix64(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ h >> 33
+}
+
+impl<H: Hasher<u8>> Hasher<u8> for Finalized<H> {
+    fn hash(&self, value: u8) -> u8 {
+        fmix32(u32::from(self.hasher.hash(value))) as u8
+    }
+
+    fn compress(hash: u8, upper_bound: usize) -> usize {
+        H::compress(hash, upper_bound)
+    }
+}
+
+impl<H: Hasher<i8>> Hasher<i8> for Finalized<H> {
+    fn hash(&self, value: i8) -> i8 {
+        fmix32(self.hasher.hash(value) as u32) as i8
+    }
+
+    fn compress(hash: i8, upper_bound: usize) -> usize {
+        H::compress(hash, upper_bound)
+    }
+}
+
+impl<H: Hasher<u16>> Hasher<u16> for Finalized<H> {
+    fn hash(&self, value: u16) -> u16 {
+        fmix32(u32::from(self.hasher.hash(value))) as u16
+    }
+
+    fn compress(hash: u16, upper_bound: usize) -> usize {
+        H::compress(hash, upper_bound)
+    }
+}
+
+impl<H: Hasher<i16>> Hasher<i16> for Finalized<H> {
+    fn hash(&self, value: i16) -> i16 {
+        fmix32(self.hasher.hash(value) as u32) as i16
+    }
+
+    fn compress(hash: i16, upper_bound: usize) -> usize {
+        H::compress(hash, upper_bound)
+    }
+}
+
+impl<H: Hasher<u32>> Hasher<u32> for Finalized<H> {
+    fn hash(&self, value: u32) -> u32 {
+        fmix32(self.hasher.hash(value))
+    }
+
+    fn compress(hash: u32, upper_bound: usize) -> usize {
+        H::compress(hash, upper_bound)
+    }
+}
+
+impl<H: Hasher<i32>> Hasher<i32> for Finalized<H> {
+    fn hash(&self, value: i32) -> i32 {
+        fmix32(self.hasher.hash(value) as u32) as i32
+    }
+
+    fn compress(hash: i32, upper_bound: usize) -> usize {
+        H::compress(hash, upper_bound)
+    }
+}
+
+impl<H: Hasher<u64>> Hasher<u64> for Finalized<H> {
+    fn hash(&self, value: u64) -> u64 {
+        fmix64(self.hasher.hash(value))
+    }
+
+    fn compress(hash: u64, upper_bound: usize) -> usize {
+        H::compress(hash, upper_bound)
+    }
+}
+
+impl<H: Hasher<usize>> Hasher<usize> for Finalized<H> {
+    fn hash(&self, value: usize) -> usize {
+        fmix64(self.hasher.hash(value) as u64) as usize
+    }
+
+    fn compress(hash: usize, upper_bound: usize) -> usize {
+        H::compress(hash, upper_bound)
+    }
+}
@@ -0,0 +1,61 @@
+use {Bucket, BucketList, Hasher, Key, PrimitiveMap, Value};
+
+/// Moves the entries out of a map bucket by bucket, without allocating.
+pub struct IntoIter<K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    map: PrimitiveMap<K, V, B, BL, H>,
+    next_bucket: usize,
+}
+
+impl<K, V, B, BL, H> Iterator for IntoIter<K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        while self.next_bucket < self.map.buckets.len() {
+            let bucket = self.map.buckets.get_mut(self.next_bucket);
+            let first = bucket.iter().next();
+            if let Some((key, _)) = first {
+                self.map.len -= 1;
+                return bucket.take(key);
+            }
+            self.next_bucket += 1;
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.map.len, Some(self.map.len))
+    }
+}
+
+impl<K, V, B, BL, H> IntoIterator for PrimitiveMap<K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, B, BL, H>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            map: self,
+            next_bucket: 0,
+        }
+    }
+}
@@ -13,3 +13,126 @@ where
     T: Sized + Copy + Default,
 {
 }
+
+pub trait IntegerKey: Key + PartialOrd {
+    fn successor(self) -> Self;
+    fn to_u64(self) -> u64;
+}
+
+impl IntegerKey for u8 {
+    fn successor(self) -> Self {
+        self + 1
+    }
+
+    fn to_u64(self) -> u64 {
+        u64::from(self)
+    }
+}
+
+impl IntegerKey for i8 {
+    fn successor(self) -> Self {
+        self + 1
+    }
+
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+}
+
+impl IntegerKey for u16 {
+    fn successor(self) -> Self {
+        self + 1
+    }
+
+    fn to_u64(self) -> u64 {
+        u64::from(self)
+    }
+}
+
+impl IntegerKey for i16 {
+    fn successor(self) -> Self {
+        self + 1
+    }
+
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+}
+
+impl IntegerKey for u32 {
+    fn successor(self) -> Self {
+        self + 1
+    }
+
+    fn to_u64(self) -> u64 {
+        u64::from(self)
+    }
+}
+
+impl IntegerKey for i32 {
+    fn successor(self) -> Self {
+        self + 1
+    }
+
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+}
+
+impl IntegerKey for u64 {
+    fn successor(self) -> Self {
+        self + 1
+    }
+
+    fn to_u64(self) -> u64 {
+        self
+    }
+}
+
+impl IntegerKey for usize {
+    fn successor(self) -> Self {
+        self + 1
+    }
+
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+}
+
+/// Number of distinct values of a key type, for telling when every key can
+/// get a bucket of its own.
+pub trait KeyRange: Key {
+    const RANGE: u128;
+}
+
+impl KeyRange for u8 {
+    const RANGE: u128 = 1 << 8;
+}
+
+impl KeyRange for i8 {
+    const RANGE: u128 = 1 << 8;
+}
+
+impl KeyRange for u16 {
+    const RANGE: u128 = 1 << 16;
+}
+
+impl KeyRange for i16 {
+    const RANGE: u128 = 1 << 16;
+}
+
+impl KeyRange for u32 {
+    const RANGE: u128 = 1 << 32;
+}
+
+impl KeyRange for i32 {
+    const RANGE: u128 = 1 << 32;
+}
+
+impl KeyRange for u64 {
+    const RANGE: u128 = 1 << 64;
+}
+
+impl KeyRange for usize {
+    const RANGE: u128 = 1 << usize::BITS;
+}
@@ -1,15 +1,53 @@
+#[cfg(feature = "rayon")]
+extern crate rayon;
 extern crate smallvec;
 
+pub mod atomic;
 pub mod bucket;
+pub mod drain;
+pub mod entry;
+pub mod error;
+pub mod fifo;
 pub mod hash;
+pub mod iter;
 pub mod kv;
+#[cfg(feature = "rayon")]
+mod par;
+pub mod perfect;
+pub mod set;
 
+use std::cmp::Ordering;
+use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::mem;
+use std::ops::{AddAssign, Div, Mul, Sub};
+use std::ptr;
+use std::sync::Arc;
 
-pub use bucket::{ArrayBucket, ArrayBucketList, Bucket, BucketList, SmallVecBucket,
-                 SmallVecBucketList};
-pub use hash::{DefaultHasher, Hash, Hasher};
-pub use kv::{Key, Value};
+pub use atomic::AtomicPrimitiveMap;
+pub use bucket::capacity_for_const;
+pub use bucket::{ArrayBucket, ArrayBucketList, BTreeBucket, Bucket, BucketList,
+                 CountedKey, CountingBucket, GrowableBucketList, KeysOnlyBucket,
+                 KeysOnlyBucketList, SmallVecBucket, SmallVecBucketList, SoaBucket,
+                 SortedVecBucket, StableVecBucket};
+use bucket::{BUCKET_LIST_SIZE, BUCKET_SIZE};
+pub use drain::DrainFilter;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::{CapacityError, OverlapError, ProbeError};
+pub use fifo::FifoPrimitiveMap;
+pub use hash::{
+    Compress, DefaultHasher, FastHasher, Fibonacci, Finalized, Hash, Hasher, Mask, Modulo,
+    SipHasher,
+};
+pub use iter::IntoIter;
+pub use kv::{IntegerKey, Key, KeyRange, Value};
+pub use perfect::PerfectMap;
+pub use set::PrimitiveSet;
+
+pub type DynamicPrimitiveMap<K, V> =
+    PrimitiveMap<K, V, SmallVecBucket<K, V>, SmallVecBucketList<K, V>, DefaultHasher<K>>;
+pub type SecurePrimitiveMap<K, V> =
+    PrimitiveMap<K, V, SmallVecBucket<K, V>, SmallVecBucketList<K, V>, SipHasher<K>>;
 
 pub struct PrimitiveMap<
     K: Key,
@@ -19,7 +57,15 @@ pub struct PrimitiveMap<
     H: Hasher<K>,
 > {
     buckets: BL,
-    _marker: PhantomData<(K, V, B, H)>,
+    len: usize,
+    probe_limit: usize,
+    growth_factor: f64,
+    on_resize: Option<Arc<dyn Fn(usize, usize) + Send + Sync>>,
+    // Set from the growable impl, where rehashing is available, so that
+    // removals in the generic code can shrink the map.
+    auto_shrink: Option<fn(&mut Self)>,
+    hasher: H,
+    _marker: PhantomData<(K, V, B)>,
 }
 
 impl<K, V, B, BL, H> PrimitiveMap<K, V, B, BL, H>
@@ -30,29 +76,845 @@ where
     BL: BucketList<K, V, Bucket = B>,
     H: Hasher<K>,
 {
-    pub fn custom(buckets: BL, _hasher: H) -> Self {
+    pub fn custom(buckets: BL, hasher: H) -> Self {
         PrimitiveMap {
             buckets,
+            len: 0,
+            probe_limit: usize::MAX,
+            growth_factor: 2.0,
+            on_resize: None,
+            auto_shrink: None,
+            hasher,
             _marker: PhantomData,
         }
     }
 
+    /// Builds a map around a bucket list taken from another map, e.g. one
+    /// restored byte for byte, without inserting the entries again.
+    ///
+    /// # Safety
+    ///
+    /// `buckets` must hold exactly `len` entries, each in the bucket `hasher`
+    /// maps it to or spilled past full buckets the way `insert` does it.
+    /// Otherwise lookups miss entries and the entry count goes wrong.
+    pub unsafe fn from_raw_buckets(buckets: BL, len: usize, hasher: H) -> Self {
+        let mut map = PrimitiveMap::custom(buckets, hasher);
+        map.len = len;
+        map
+    }
+
+    pub fn raw_buckets(&self) -> &BL {
+        &self.buckets
+    }
+
+    pub fn with_probe_limit(mut self, probe_limit: usize) -> Self {
+        self.probe_limit = probe_limit;
+        self
+    }
+
+    /// Sets how much `reserve` multiplies the bucket count by when the map
+    /// has to grow. Defaults to 2.
+    pub fn with_growth_factor(mut self, growth_factor: f64) -> Self {
+        assert!(growth_factor > 1.0, "growth factor must be greater than 1");
+        self.growth_factor = growth_factor;
+        self
+    }
+
+    /// Calls `on_resize` with the old and the new bucket count whenever the
+    /// bucket count changes, i.e. when `reserve` or `reserve_exact` grow the
+    /// map and when `shrink_if_sparse` or `clear_and_shrink` shrink it.
+    /// Clones of the map share the callback through an `Arc`, which is why
+    /// it has to be `Fn + Send + Sync` rather than `FnMut`. Keep counters in
+    /// atomics or behind a `Mutex`.
+    pub fn with_resize_callback<F>(mut self, on_resize: F) -> Self
+    where
+        F: Fn(usize, usize) + Send + Sync + 'static,
+    {
+        self.on_resize = Some(Arc::new(on_resize));
+        self
+    }
+
     pub fn insert(&mut self, key: K, value: V) {
+        if let Err(err) = self.try_insert(key, value) {
+            panic!("{}", err)
+        }
+    }
+
+    pub fn extend_from_slice(&mut self, pairs: &[(K, V)]) {
+        for &(key, value) in pairs {
+            self.insert(key, value)
+        }
+    }
+
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<(), ProbeError<K, V>> {
+        let addr = self.get_addr(key);
+        self.push_vacant(addr, key, value).map(|_| ())
+    }
+
+    /// Returns `false` and leaves the map untouched if `key` is present.
+    pub fn insert_if_absent(&mut self, key: K, value: V) -> bool {
         let addr = self.get_addr(key);
-        let bucket = self.buckets.get_mut(addr);
-        bucket.push(key, value)
+        if self.find_addr(addr, key).is_some() {
+            return false;
+        }
+        if let Err(err) = self.push_vacant(addr, key, value) {
+            panic!("{}", err)
+        }
+        true
     }
 
     pub fn get(&self, key: K) -> Option<V> {
         let addr = self.get_addr(key);
-        let bucket = &self.buckets.get(addr);
-        bucket.get(key)
+        let len = self.buckets.len();
+        for offset in 0..len {
+            let bucket = self.buckets.get((addr + offset) % len);
+            let value = bucket.get(key);
+            if value.is_some() || !bucket.reached_max_capacity() {
+                return value;
+            }
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: K) -> bool {
+        let addr = self.get_addr(key);
+        let len = self.buckets.len();
+        for offset in 0..len {
+            let bucket = self.buckets.get((addr + offset) % len);
+            if bucket.contains(key) {
+                return true;
+            }
+            if !bucket.reached_max_capacity() {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Like `get`, but also returns how many buckets were looked at, 1 for
+    /// keys in their home bucket.
+    pub fn get_with_probe(&self, key: K) -> Option<(V, usize)> {
+        let addr = self.get_addr(key);
+        let len = self.buckets.len();
+        for offset in 0..len {
+            let bucket = self.buckets.get((addr + offset) % len);
+            if let Some(value) = bucket.get(key) {
+                return Some((value, offset + 1));
+            }
+            if !bucket.reached_max_capacity() {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Like `get`, but scans from the home bucket straight to the entry,
+    /// skipping the checks for where a lookup of a missing key can stop.
+    ///
+    /// # Safety
+    ///
+    /// `key` must be present in the map, otherwise the behavior is undefined.
+    pub unsafe fn get_unchecked(&self, key: K) -> V {
+        let len = self.buckets.len();
+        let mut idx = self.get_addr(key);
+        for _ in 0..len {
+            if let Some(value) = self.buckets.get(idx).get(key) {
+                return value;
+            }
+            idx += 1;
+            if idx == len {
+                idx = 0;
+            }
+        }
+        debug_assert!(false, "get_unchecked called with a missing key");
+        // SAFETY: the caller guarantees that `key` is in one of the buckets.
+        std::hint::unreachable_unchecked()
+    }
+
+    pub fn expect(&self, key: K, msg: &str) -> V
+    where
+        K: Debug,
+    {
+        match self.get(key) {
+            Some(value) => value,
+            None => panic!("{}: key {:?} is missing", msg, key),
+        }
+    }
+
+    /// Looks up several distinct keys at once. Checking that they are
+    /// distinct is quadratic in the number of keys.
+    pub fn get_many_mut(&mut self, keys: &[K]) -> Result<Vec<Option<&mut V>>, OverlapError<K>> {
+        for (idx, &key) in keys.iter().enumerate() {
+            if keys[..idx].contains(&key) {
+                return Err(OverlapError { key });
+            }
+        }
+
+        let mut targets: Vec<(usize, usize)> = keys
+            .iter()
+            .enumerate()
+            .filter_map(|(pos, &key)| {
+                let addr = self.get_addr(key);
+                self.find_addr(addr, key).map(|addr| (addr, pos))
+            })
+            .collect();
+        targets.sort_unstable();
+
+        // Walking the buckets in address order hands out each bucket once,
+        // so the references never alias.
+        let mut values: Vec<Option<&mut V>> = keys.iter().map(|_| None).collect();
+        let mut buckets = self.buckets.buckets_mut();
+        let mut next = 0;
+        for group in targets.chunk_by(|a, b| a.0 == b.0) {
+            let addr = group[0].0;
+            let bucket = buckets.nth(addr - next).unwrap();
+            next = addr + 1;
+            for (key, value) in bucket.iter_mut() {
+                if let Some(&(_, pos)) = group.iter().find(|&&(_, pos)| keys[pos] == key) {
+                    values[pos] = Some(value);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    pub fn get_or_insert_with_key<F: FnOnce(K) -> V>(&mut self, key: K, f: F) -> &mut V {
+        let addr = self.get_addr(key);
+        // Nothing spills past a bucket that still has room, so the bucket
+        // can look up and insert on its own.
+        if !self.buckets.get(addr).reached_max_capacity() {
+            let mut inserted = false;
+            let value = self.buckets.get_mut(addr).get_or_insert_with(key, || {
+                inserted = true;
+                f(key)
+            });
+            if inserted {
+                self.len += 1;
+            }
+            return value;
+        }
+
+        let addr = match self.find_addr(addr, key) {
+            Some(addr) => addr,
+            None => match self.push_vacant(addr, key, f(key)) {
+                Ok(addr) => addr,
+                Err(err) => panic!("{}", err),
+            },
+        };
+        self.buckets.get_mut(addr).get_mut(key).unwrap()
+    }
+
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        self.get_or_insert_with_key(key, |_| f())
+    }
+
+    pub fn get_or_insert_default(&mut self, key: K) -> &mut V {
+        self.get_or_insert_with_key(key, |_| V::default())
+    }
+
+    /// Applies `f` to the value of `key` if present, otherwise inserts
+    /// `default` as is.
+    pub fn modify_or_insert<F: FnOnce(&mut V)>(&mut self, key: K, default: V, f: F) {
+        let addr = self.get_addr(key);
+        match self.find_addr(addr, key) {
+            Some(addr) => f(self.buckets.get_mut(addr).get_mut(key).unwrap()),
+            None => {
+                if let Err(err) = self.push_vacant(addr, key, default) {
+                    panic!("{}", err)
+                }
+            }
+        }
+    }
+
+    pub fn add_assign(&mut self, key: K, delta: V)
+    where
+        V: AddAssign,
+    {
+        let addr = self.get_addr(key);
+        match self.find_addr(addr, key) {
+            Some(addr) => *self.buckets.get_mut(addr).get_mut(key).unwrap() += delta,
+            None => {
+                if let Err(err) = self.push_vacant(addr, key, delta) {
+                    panic!("{}", err)
+                }
+            }
+        }
+    }
+
+    pub fn take(&mut self, key: K) -> Option<(K, V)> {
+        let addr = self.get_addr(key);
+        let addr = self.find_addr(addr, key)?;
+        self.take_at(addr, key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Checks every bucket instead of trusting the entry counter, meant for
+    /// validating internal consistency.
+    pub fn scan_is_empty(&self) -> bool {
+        (0..self.buckets.len()).all(|idx| self.buckets.get(idx).is_empty())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        (0..self.buckets.len()).flat_map(move |idx| self.buckets.get(idx).iter())
+    }
+
+    pub fn keys_where<'a, F>(&'a self, mut f: F) -> impl Iterator<Item = K> + 'a
+    where
+        F: FnMut(K, V) -> bool + 'a,
+    {
+        self.iter()
+            .filter(move |&(key, value)| f(key, value))
+            .map(|(key, _)| key)
+    }
+
+    pub fn values_where<'a, F>(&'a self, mut f: F) -> impl Iterator<Item = V> + 'a
+    where
+        F: FnMut(K, V) -> bool + 'a,
+    {
+        self.iter()
+            .filter(move |&(key, value)| f(key, value))
+            .map(|(_, value)| value)
+    }
+
+    pub fn for_each_mut<F: FnMut(K, &mut V)>(&mut self, mut f: F) {
+        for idx in 0..self.buckets.len() {
+            for (key, value) in self.buckets.get_mut(idx).iter_mut() {
+                f(key, value)
+            }
+        }
+    }
+
+    pub fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut f: F) {
+        let mut spilled = false;
+        for idx in 0..self.buckets.len() {
+            let bucket = self.buckets.get_mut(idx);
+            let was_full = bucket.reached_max_capacity();
+            let removed = bucket.retain(&mut f);
+            spilled |= was_full && removed > 0;
+            self.len -= removed;
+        }
+        if spilled {
+            self.replace_spilled();
+        }
+        self.maybe_shrink();
+    }
+
+    /// Counts the entries per bin of `bin_width` values, keyed by the
+    /// lowest value of the bin, so `-3` goes to the bin starting at `-10`
+    /// for a width of 10. Panics unless `bin_width` is positive.
+    pub fn value_histogram(&self, bin_width: V) -> DynamicPrimitiveMap<V, usize>
+    where
+        V: Key + PartialOrd + Div<Output = V> + Mul<Output = V> + Sub<Output = V>,
+        DefaultHasher<V>: Hasher<V>,
+    {
+        assert!(bin_width > V::default(), "bin_width must be positive");
+        let mut histogram = PrimitiveMap::dynamic();
+        for (_, value) in self.iter() {
+            // Division truncates towards zero, which is one bin too high
+            // for negative values that aren't on a bin boundary.
+            let mut bin = value / bin_width * bin_width;
+            if bin > value {
+                bin = bin - bin_width;
+            }
+            *histogram.get_or_insert_default(bin) += 1;
+        }
+        histogram
+    }
+
+    /// Consumes the map and collects every pair `f` produces into a new
+    /// map. A key produced more than once keeps the last value.
+    pub fn flat_map<W, I, F>(self, mut f: F) -> DynamicPrimitiveMap<K, W>
+    where
+        W: Value,
+        I: IntoIterator<Item = (K, W)>,
+        F: FnMut(K, V) -> I,
+        DefaultHasher<K>: Hasher<K>,
+    {
+        let mut map = PrimitiveMap::dynamic();
+        for (key, value) in self {
+            for (key, value) in f(key, value) {
+                *map.get_or_insert_default(key) = value;
+            }
+        }
+        map
+    }
+
+    pub fn max_by<F: FnMut(&V, &V) -> Ordering>(&self, mut compare: F) -> Option<(K, V)> {
+        self.iter().max_by(|a, b| compare(&a.1, &b.1))
+    }
+
+    pub fn min_by<F: FnMut(&V, &V) -> Ordering>(&self, mut compare: F) -> Option<(K, V)> {
+        self.iter().min_by(|a, b| compare(&a.1, &b.1))
+    }
+
+    pub fn into_sorted_vec(self) -> Vec<(K, V)>
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<(K, V)> = self.iter().collect();
+        entries.sort_by_key(|&(key, _)| key);
+        entries
+    }
+
+    /// Collects and sorts all keys before yielding the first one, so even a
+    /// partial iteration costs a full sort.
+    pub fn keys_sorted(&self) -> impl Iterator<Item = K>
+    where
+        K: Ord,
+    {
+        let mut keys: Vec<K> = self.iter().map(|(key, _)| key).collect();
+        keys.sort_unstable();
+        keys.into_iter()
+    }
+
+    pub fn bucket_index(&self, key: K) -> usize {
+        self.get_addr(key)
+    }
+
+    /// Whether there are at least as many buckets as possible keys. With an
+    /// identity hash, as `DefaultHasher` uses for `u8` and `i8`, every key
+    /// then gets a bucket of its own.
+    pub fn covers_key_range(&self) -> bool
+    where
+        K: KeyRange,
+    {
+        self.buckets.len() as u128 >= K::RANGE
+    }
+
+    /// The hash of `key` before it is compressed into a bucket address.
+    pub fn hash_key(&self, key: K) -> K {
+        self.hasher.hash(key)
+    }
+
+    pub fn reserve_buckets(&mut self, counts: &[usize]) {
+        for (idx, &count) in counts.iter().enumerate().take(self.buckets.len()) {
+            self.buckets.get_mut(idx).reserve(count)
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for idx in 0..self.buckets.len() {
+            self.buckets.get_mut(idx).clear()
+        }
+        self.len = 0;
+    }
+
+    pub fn capacity(&self) -> usize {
+        (0..self.buckets.len())
+            .map(|idx| self.buckets.get(idx).capacity())
+            .sum()
+    }
+
+    /// Moves all entries into a map backed by another bucket list type,
+    /// typically a larger array. Panics if they don't fit.
+    pub fn grow_to<BL2>(self) -> PrimitiveMap<K, V, B, BL2, H>
+    where
+        BL2: BucketList<K, V, Bucket = B>,
+    {
+        let mut map = PrimitiveMap::custom(BL2::empty(), self.hasher);
+        map.probe_limit = self.probe_limit;
+        map.growth_factor = self.growth_factor;
+        map.on_resize = self.on_resize;
+        for idx in 0..self.buckets.len() {
+            for (key, value) in self.buckets.get(idx).iter() {
+                map.insert(key, value)
+            }
+        }
+        map
+    }
+
+    /// Copies the contents of `self` into `target`, reusing the buckets
+    /// of `target` when both maps have the same number of buckets.
+    pub fn clone_into(&self, target: &mut Self)
+    where
+        BL: Clone,
+        H: Clone,
+    {
+        target.len = self.len;
+        target.probe_limit = self.probe_limit;
+        target.growth_factor = self.growth_factor;
+        target.on_resize = self.on_resize.clone();
+        target.auto_shrink = self.auto_shrink;
+        target.hasher = self.hasher.clone();
+        if target.buckets.len() != self.buckets.len() {
+            target.buckets = self.buckets.clone();
+            return;
+        }
+
+        for idx in 0..self.buckets.len() {
+            let source = self.buckets.get(idx);
+            let bucket = target.buckets.get_mut(idx);
+            bucket.clear();
+            for (key, value) in source.iter() {
+                bucket.push(key, value)
+            }
+        }
+    }
+
+    /// Looks up every key in `[lo, hi)` one by one, so the cost is
+    /// proportional to the width of the range, not to the number of entries.
+    pub fn get_range(&self, lo: K, hi: K) -> Vec<(K, V)>
+    where
+        K: IntegerKey,
+    {
+        let mut entries = Vec::new();
+        let mut key = lo;
+        while key < hi {
+            if let Some(value) = self.get(key) {
+                entries.push((key, value));
+            }
+            key = key.successor();
+        }
+        entries
     }
 
     fn get_addr(&self, key: K) -> usize {
-        let hash = H::hash(key);
+        let hash = self.hasher.hash(key);
         H::compress(hash, self.buckets.len())
     }
+
+    // Buckets that reached their max capacity spill into the next ones,
+    // so a key lives somewhere between its address and the first bucket
+    // that still has room.
+    fn find_addr(&self, addr: usize, key: K) -> Option<usize> {
+        let len = self.buckets.len();
+        for offset in 0..len {
+            let idx = (addr + offset) % len;
+            let bucket = self.buckets.get(idx);
+            if bucket.get(key).is_some() {
+                return Some(idx);
+            }
+            if !bucket.reached_max_capacity() {
+                return None;
+            }
+        }
+        None
+    }
+
+    // Like `find_addr`, but also returns the position of the entry within
+    // its bucket when the bucket can tell it without extra work.
+    fn find_slot(&mut self, addr: usize, key: K) -> Option<(usize, Option<usize>)> {
+        if !B::CHEAP_POSITIONS {
+            return self.find_addr(addr, key).map(|addr| (addr, None));
+        }
+        let len = self.buckets.len();
+        for offset in 0..len {
+            let idx = (addr + offset) % len;
+            let bucket = self.buckets.get_mut(idx);
+            if let Some((index, _)) = bucket.find_mut(key) {
+                return Some((idx, Some(index)));
+            }
+            if !bucket.reached_max_capacity() {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn push_vacant(&mut self, addr: usize, key: K, value: V) -> Result<usize, ProbeError<K, V>> {
+        match self.vacant_addr(addr) {
+            Some(addr) => {
+                self.buckets.get_mut(addr).push(key, value);
+                self.len += 1;
+                Ok(addr)
+            }
+            None => Err(ProbeError {
+                key,
+                value,
+                probes: self.max_probes(),
+            }),
+        }
+    }
+
+    fn vacant_addr(&self, addr: usize) -> Option<usize> {
+        let len = self.buckets.len();
+        (0..self.max_probes())
+            .map(|offset| (addr + offset) % len)
+            .find(|&idx| !self.buckets.get(idx).reached_max_capacity())
+    }
+
+    fn replace_spilled(&mut self) {
+        let len = self.buckets.len();
+        self.replace_spilled_in(0, len)
+    }
+
+    // Like `replace_spilled`, but only for the `count` buckets from `start`
+    // on, wrapping around.
+    fn replace_spilled_in(&mut self, start: usize, count: usize) {
+        let len = self.buckets.len();
+        let hasher = &self.hasher;
+        let mut spilled = Vec::new();
+        for idx in (start..start + count).map(|idx| idx % len) {
+            let bucket = self.buckets.get_mut(idx);
+            spilled.extend(bucket.drain_filter(|key, _| H::compress(hasher.hash(key), len) != idx));
+        }
+        for (key, value) in spilled {
+            self.len -= 1;
+            let addr = self.get_addr(key);
+            if let Err(err) = self.push_vacant(addr, key, value) {
+                panic!("{}", err)
+            }
+        }
+    }
+
+    fn take_at(&mut self, addr: usize, key: K) -> Option<(K, V)> {
+        let was_full = self.buckets.get(addr).reached_max_capacity();
+        let entry = self.buckets.get_mut(addr).take(key)?;
+        self.after_removal(addr, was_full);
+        Some(entry)
+    }
+
+    fn remove_slot(&mut self, addr: usize, index: usize) -> (K, V) {
+        let was_full = self.buckets.get(addr).reached_max_capacity();
+        let entry = self.buckets.get_mut(addr).remove_at(index);
+        self.after_removal(addr, was_full);
+        entry
+    }
+
+    fn after_removal(&mut self, addr: usize, was_full: bool) {
+        self.len -= 1;
+        if was_full {
+            self.fill_hole(addr);
+        }
+        self.maybe_shrink();
+    }
+
+    fn maybe_shrink(&mut self) {
+        if let Some(shrink) = self.auto_shrink {
+            shrink(self)
+        }
+    }
+
+    fn max_probes(&self) -> usize {
+        self.probe_limit.min(self.buckets.len())
+    }
+
+    // After taking an entry out of a full bucket, pull back entries that
+    // spilled past it, otherwise the lookup would stop at the hole.
+    fn fill_hole(&mut self, mut hole: usize) {
+        let len = self.buckets.len();
+        let distance = |from: usize, to: usize| (to + len - from) % len;
+        let mut idx = hole;
+        loop {
+            idx = (idx + 1) % len;
+            if idx == hole {
+                return;
+            }
+
+            let (spilled, full) = {
+                let bucket = self.buckets.get(idx);
+                let spilled = bucket
+                    .iter()
+                    .find(|&(key, _)| distance(self.get_addr(key), idx) >= distance(hole, idx));
+                (spilled, bucket.reached_max_capacity())
+            };
+
+            match spilled {
+                Some((key, value)) => {
+                    self.buckets.get_mut(idx).take(key);
+                    self.buckets.get_mut(hole).push(key, value);
+                    hole = idx;
+                }
+                None if !full => return,
+                None => {}
+            }
+        }
+    }
+}
+
+// Entries can sit in different buckets when they were inserted in a
+// different order and spilled, so a bucket-by-bucket mismatch falls back to
+// looking up every key.
+impl<K, V, B, BL, H> PartialEq for PrimitiveMap<K, V, B, BL, H>
+where
+    K: Key,
+    V: Value + PartialEq,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        if self.len != other.len {
+            return false;
+        }
+        if self.buckets.len() == other.buckets.len()
+            && (0..self.buckets.len())
+                .all(|idx| self.buckets.get(idx).eq_entries(other.buckets.get(idx)))
+        {
+            return true;
+        }
+        self.iter()
+            .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K, V, B, BL, H> PrimitiveMap<K, V, CountingBucket<B>, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<CountedKey<K>, V>,
+    BL: BucketList<K, V, Bucket = CountingBucket<B>>,
+    H: Hasher<K>,
+{
+    /// Key comparisons made by lookups and inserts in all buckets so far.
+    pub fn comparisons(&self) -> u64 {
+        (0..self.buckets.len())
+            .map(|idx| self.buckets.get(idx).comparisons())
+            .sum()
+    }
+
+    pub fn reset_comparisons(&self) {
+        for idx in 0..self.buckets.len() {
+            self.buckets.get(idx).reset_comparisons()
+        }
+    }
+}
+
+impl<K, V, B, BL, H> Clone for PrimitiveMap<K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: BucketList<K, V, Bucket = B> + Clone,
+    H: Hasher<K> + Clone,
+{
+    fn clone(&self) -> Self {
+        PrimitiveMap {
+            buckets: self.buckets.clone(),
+            len: self.len,
+            probe_limit: self.probe_limit,
+            growth_factor: self.growth_factor,
+            on_resize: self.on_resize.clone(),
+            auto_shrink: self.auto_shrink,
+            hasher: self.hasher.clone(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        source.clone_into(self)
+    }
+}
+
+impl<K, V, B, BL, H> PrimitiveMap<K, V, B, BL, H>
+where
+    K: Key,
+    V: Value,
+    B: Bucket<K, V>,
+    BL: GrowableBucketList<K, V, Bucket = B>,
+    H: Hasher<K>,
+{
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = Self::buckets_for(self.len + additional);
+        let mut buckets = self.buckets.len().max(1);
+        if needed > self.buckets.len() {
+            while buckets < needed {
+                buckets = (buckets as f64 * self.growth_factor).ceil() as usize;
+            }
+            self.rehash(buckets)
+        }
+    }
+
+    /// Unlike `clear`, also gives back the memory of the buckets by going
+    /// back to the default bucket list.
+    pub fn clear_and_shrink(&mut self) {
+        let old = mem::replace(&mut self.buckets, BL::empty());
+        self.len = 0;
+        if let Some(ref on_resize) = self.on_resize {
+            if old.len() != self.buckets.len() {
+                on_resize(old.len(), self.buckets.len());
+            }
+        }
+    }
+
+    /// When enabled, removals that leave fewer than one entry per eight
+    /// slots shrink the map with `shrink_if_sparse`.
+    pub fn with_auto_shrink(mut self, enabled: bool) -> Self {
+        self.auto_shrink = if enabled {
+            Some(Self::shrink_if_sparse)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Rehashes into fewer buckets if fewer than one in eight slots is used,
+    /// but never below the default bucket count.
+    pub fn shrink_if_sparse(&mut self) {
+        let slots = self.buckets.len() * BUCKET_SIZE;
+        if self.buckets.len() <= BUCKET_LIST_SIZE || self.len >= slots / 8 {
+            return;
+        }
+        let buckets = Self::buckets_for(self.len * 2).next_power_of_two();
+        self.rehash(buckets.max(BUCKET_LIST_SIZE))
+    }
+
+    pub fn reserve_exact(&mut self, additional: usize) {
+        let buckets = Self::buckets_for(self.len + additional);
+        if buckets > self.buckets.len() {
+            self.rehash(buckets)
+        }
+    }
+
+    // Keeps at most BUCKET_SIZE entries per bucket, so that buckets with
+    // inline storage do not spill.
+    fn buckets_for(entries: usize) -> usize {
+        entries.div_ceil(BUCKET_SIZE)
+    }
+
+    // Target addresses are known up front, so entries are grouped by bucket
+    // and appended in one go. Only what doesn't fit goes through probing.
+    fn rehash(&mut self, buckets: usize) {
+        let old = mem::replace(&mut self.buckets, BL::with_len(buckets));
+        if let Some(ref on_resize) = self.on_resize {
+            on_resize(old.len(), buckets);
+        }
+        let len = self.len;
+
+        let mut entries: Vec<(usize, K, V)> = (0..old.len())
+            .flat_map(|idx| old.get(idx).iter())
+            .map(|(key, value)| (self.get_addr(key), key, value))
+            .collect();
+        entries.sort_by_key(|&(addr, _, _)| addr);
+
+        for group in entries.chunk_by(|a, b| a.0 == b.0) {
+            let addr = group[0].0;
+            let mut group = group.iter().map(|&(_, key, value)| (key, value));
+            self.buckets.get_mut(addr).extend_from(&mut group);
+            for (key, value) in group {
+                if let Err(err) = self.push_vacant(addr, key, value) {
+                    panic!("{}", err)
+                }
+            }
+        }
+
+        self.len = len;
+    }
+}
+
+impl<K, V> PrimitiveMap<K, V, SmallVecBucket<K, V>, SmallVecBucketList<K, V>, SipHasher<K>>
+where
+    K: Key,
+    V: Value,
+    SipHasher<K>: Hasher<K>,
+{
+    pub fn secure() -> Self {
+        PrimitiveMap::custom(SmallVecBucketList::empty(), SipHasher::new())
+    }
+
+    /// A `secure` map seeded with the fixed keys `k0 = 0, k1 = 0`, so that
+    /// bucket placement is reproducible across runs and machines. Meant for
+    /// tests and fuzzing, it gives no protection against collision attacks.
+    pub fn deterministic() -> Self {
+        PrimitiveMap::custom(SmallVecBucketList::empty(), SipHasher::with_keys(0, 0))
+    }
 }
 
 impl<K, V> PrimitiveMap<K, V, SmallVecBucket<K, V>, SmallVecBucketList<K, V>, DefaultHasher<K>>
@@ -64,6 +926,29 @@ where
     pub fn dynamic() -> Self {
         PrimitiveMap::custom(SmallVecBucketList::empty(), DefaultHasher::default())
     }
+
+    /// Reserves room for `per_bucket` entries in each of the `buckets`
+    /// buckets up front, so that loading keys doesn't regrow them one by one.
+    pub fn with_capacity_and_bucket_hint(buckets: usize, per_bucket: usize) -> Self {
+        let mut map = PrimitiveMap::custom(
+            SmallVecBucketList::with_len(buckets),
+            DefaultHasher::default(),
+        );
+        for idx in 0..buckets {
+            map.buckets.get_mut(idx).reserve(per_bucket)
+        }
+        map
+    }
+}
+
+impl<K> PrimitiveMap<K, (), KeysOnlyBucket<K>, KeysOnlyBucketList<K>, DefaultHasher<K>>
+where
+    K: Key,
+    DefaultHasher<K>: Hasher<K>,
+{
+    pub fn keys_only() -> Self {
+        PrimitiveMap::custom(KeysOnlyBucketList::empty(), DefaultHasher::default())
+    }
 }
 
 impl<K, V> PrimitiveMap<K, V, ArrayBucket<K, V>, ArrayBucketList<K, V>, DefaultHasher<K>>
@@ -97,6 +982,20 @@ where
     pub fn fixed_with_hasher(hasher: H) -> Self {
         PrimitiveMap::custom(ArrayBucketList::empty(), hasher)
     }
+
+    /// Touches every bucket so that the pages backing the array are faulted
+    /// in before a latency-sensitive phase. This is only a performance hint,
+    /// the contents of the map are not changed.
+    pub fn prefault(&mut self) {
+        for idx in 0..self.buckets.len() {
+            let bucket = self.buckets.get_mut(idx);
+            // SAFETY: `bucket` comes from a `&mut`, so it is valid, aligned
+            // and not aliased, and `ArrayBucket` is `Copy`, so writing its
+            // own value back neither drops nor duplicates anything. The
+            // write is volatile only so that it isn't optimized away.
+            unsafe { ptr::write_volatile(bucket, *bucket) }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -170,4 +1069,1772 @@ mod tests {
             map.insert(i, 10u32);
         }
     }
+
+    #[test]
+    fn clone_into_reuses_buckets() {
+        let mut source = PrimitiveMap::dynamic();
+        let mut target = PrimitiveMap::dynamic();
+        for i in 0..10000u32 {
+            source.insert(i, i * 2);
+            target.insert(i, 0);
+        }
+        let capacity = target.capacity();
+
+        source.clone_into(&mut target);
+        assert_eq!(target.capacity(), capacity);
+        for i in 0..10000u32 {
+            assert_eq!(target.get(i), Some(i * 2));
+        }
+    }
+
+    #[test]
+    fn hash_many_matches_hash() {
+        let keys: Vec<u32> = (0..1000).collect();
+        let hasher = DefaultHasher::<u32>::default();
+        let hashes = hasher.hash_many(&keys);
+        for (&key, &hash) in keys.iter().zip(hashes.iter()) {
+            assert_eq!(hasher.hash(key), hash);
+        }
+    }
+
+    #[test]
+    fn take_dynamic() {
+        let mut map = PrimitiveMap::dynamic();
+        map.insert(1u32, 10u32);
+        map.insert(2u32, 20u32);
+        assert_eq!(map.take(1), Some((1, 10)));
+        assert_eq!(map.take(1), None);
+        assert_eq!(map.get(2), Some(20));
+    }
+
+    #[test]
+    fn take_fixed() {
+        let mut map = PrimitiveMap::fixed();
+        map.insert(1u32, 10u32);
+        map.insert(2u32, 20u32);
+        assert_eq!(map.take(2), Some((2, 20)));
+        assert_eq!(map.take(2), None);
+        assert_eq!(map.get(1), Some(10));
+    }
+
+    #[test]
+    fn get_or_insert_with_key_memoizes() {
+        let mut map = PrimitiveMap::dynamic();
+        let mut calls = 0;
+        for &key in &[2u64, 3, 2, 5, 3, 2] {
+            let value = *map.get_or_insert_with_key(key, |k| {
+                calls += 1;
+                k.pow(2)
+            });
+            assert_eq!(value, key.pow(2));
+        }
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn get_range_sparse_keys() {
+        let mut map = PrimitiveMap::fixed();
+        for &key in &[1u16, 5, 9, 12, 20, 31] {
+            map.insert(key, key * 10);
+        }
+        assert_eq!(map.get_range(5, 20), vec![(5, 50), (9, 90), (12, 120)]);
+        assert_eq!(map.get_range(13, 20), vec![]);
+    }
+
+    #[test]
+    fn overflow_into_next_bucket_fixed() {
+        let mut map = PrimitiveMap::fixed();
+        let addr = map.get_addr(0u16);
+        let keys: Vec<u16> = (0..u16::MAX)
+            .filter(|&key| map.get_addr(key) == addr)
+            .take(BUCKET_SIZE + 1)
+            .collect();
+        for &key in &keys {
+            map.insert(key, u32::from(key));
+        }
+
+        let spilled = *keys.last().unwrap();
+        assert_eq!(
+            map.buckets.get(addr + 1).get(spilled),
+            Some(u32::from(spilled))
+        );
+        for &key in &keys {
+            assert_eq!(map.get(key), Some(u32::from(key)));
+        }
+
+        assert_eq!(map.take(keys[0]), Some((keys[0], u32::from(keys[0]))));
+        assert_eq!(map.buckets.get(addr).get(spilled), Some(u32::from(spilled)));
+        for &key in &keys[1..] {
+            assert_eq!(map.get(key), Some(u32::from(key)));
+        }
+    }
+
+    #[test]
+    fn add_assign_accumulates() {
+        let mut map = PrimitiveMap::dynamic();
+        let samples = [
+            (1u32, 0.5f64),
+            (2, 1.25),
+            (1, 2.0),
+            (3, -1.0),
+            (2, 0.75),
+            (1, 0.25),
+        ];
+        for &(key, weight) in &samples {
+            map.add_assign(key, weight * 2.0);
+        }
+        assert_eq!(map.get(1), Some(5.5));
+        assert_eq!(map.get(2), Some(4.0));
+        assert_eq!(map.get(3), Some(-2.0));
+        assert_eq!(map.get(4), None);
+    }
+
+    #[test]
+    fn prefault_fixed() {
+        let mut map = PrimitiveMap::fixed();
+        let mut prefaulted = PrimitiveMap::fixed();
+        prefaulted.prefault();
+        for i in 0..3000u32 {
+            map.insert(i, i + 1);
+            prefaulted.insert(i, i + 1);
+        }
+        prefaulted.prefault();
+        for i in 0..4000u32 {
+            assert_eq!(prefaulted.get(i), map.get(i));
+        }
+    }
+
+    #[test]
+    fn try_insert_probe_limit() {
+        let mut map = PrimitiveMap::fixed().with_probe_limit(2);
+        let addr = map.get_addr(0u16);
+        let keys: Vec<u16> = (0..u16::MAX)
+            .filter(|&key| map.get_addr(key) == addr)
+            .take(BUCKET_SIZE * 2 + 1)
+            .collect();
+        for &key in &keys[..BUCKET_SIZE * 2] {
+            assert_eq!(map.try_insert(key, 1u32), Ok(()));
+        }
+
+        let key = keys[BUCKET_SIZE * 2];
+        let err = map.try_insert(key, 2).unwrap_err();
+        assert_eq!(err.key, key);
+        assert_eq!(err.value, 2);
+        assert_eq!(err.probes, 2);
+        assert_eq!(map.get(key), None);
+    }
+
+    #[test]
+    fn into_sorted_vec_ascending() {
+        let mut map = PrimitiveMap::dynamic();
+        for &key in &[907u32, 3, 51000, 42, 7, 2048, 0, 65] {
+            map.insert(key, key + 1);
+        }
+        let entries = map.into_sorted_vec();
+        let keys: Vec<u32> = entries.iter().map(|&(key, _)| key).collect();
+        assert_eq!(keys, vec![0, 3, 7, 42, 65, 907, 2048, 51000]);
+        assert!(entries.iter().all(|&(key, value)| value == key + 1));
+    }
+
+    #[test]
+    fn reserve_exact_has_no_slack() {
+        let mut exact = PrimitiveMap::dynamic();
+        let mut rounded = PrimitiveMap::dynamic();
+        for i in 0..1000u32 {
+            exact.insert(i, i);
+            rounded.insert(i, i);
+        }
+
+        exact.reserve_exact(4000);
+        rounded.reserve(4000);
+        assert_eq!(exact.capacity(), 5000);
+        assert_eq!(rounded.capacity(), 8192);
+        for i in 0..1000u32 {
+            assert_eq!(exact.get(i), Some(i));
+            assert_eq!(rounded.get(i), Some(i));
+        }
+        assert_eq!(exact.len(), 1000);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter_sum() {
+        use rayon::prelude::*;
+
+        let mut map = PrimitiveMap::dynamic();
+        for i in 0..10000u64 {
+            map.insert(i, i * 3);
+        }
+        let sequential: u64 = map.iter().map(|(_, value)| value).sum();
+        let parallel: u64 = map.par_iter().map(|(_, value)| value).sum();
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn entry_inspect_then_modify() {
+        let mut map = PrimitiveMap::dynamic();
+        map.insert(1u32, 10u32);
+
+        let mut log = Vec::new();
+        for &key in &[1u32, 2] {
+            let entry = map.entry(key);
+            log.push((entry.key(), entry.is_occupied(), entry.get()));
+            if entry.is_occupied() {
+                *entry.or_insert(0) += 1;
+            }
+        }
+
+        assert_eq!(log, vec![(1, true, Some(10)), (2, false, None)]);
+        assert_eq!(map.get(1), Some(11));
+        assert_eq!(map.get(2), None);
+        assert_eq!(*map.entry(2).or_insert_with(|| 20), 20);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn custom_array_bucket_list() {
+        let mut map: PrimitiveMap<u16, u32, _, [ArrayBucket<_, _>; 128], _> =
+            PrimitiveMap::custom(BucketList::empty(), DefaultHasher::default());
+        for i in 0..200u16 {
+            map.insert(i, u32::from(i) * 7);
+        }
+        assert_eq!(map.capacity(), 128 * BUCKET_SIZE);
+        for i in 0..200u16 {
+            assert_eq!(map.get(i), Some(u32::from(i) * 7));
+        }
+        assert_eq!(map.get(200), None);
+    }
+
+    #[test]
+    fn expect_present() {
+        let mut map = PrimitiveMap::dynamic();
+        map.insert(42u32, 1u8);
+        assert_eq!(map.expect(42, "opcode table"), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "opcode table: key 42 is missing")]
+    fn expect_missing() {
+        let map: PrimitiveMap<u32, u8, _, _, _> = PrimitiveMap::dynamic();
+        map.expect(42, "opcode table");
+    }
+
+    #[test]
+    fn rehash_matches_insertion() {
+        let mut rehashed = PrimitiveMap::dynamic();
+        let mut inserted = PrimitiveMap::dynamic();
+        inserted.reserve_exact(100_000);
+        for i in 0..100_000u32 {
+            let key = i.wrapping_mul(2_654_435_761);
+            rehashed.insert(key, i);
+            inserted.insert(key, i);
+        }
+        rehashed.reserve_exact(100_000 - rehashed.len());
+
+        assert_eq!(rehashed.len(), inserted.len());
+        assert_eq!(rehashed.buckets.len(), inserted.buckets.len());
+        assert_eq!(rehashed.into_sorted_vec(), inserted.into_sorted_vec());
+    }
+
+    #[test]
+    fn secure_insert_and_get() {
+        let mut map: SecurePrimitiveMap<u32, u32> = PrimitiveMap::secure();
+        for i in 0..5000u32 {
+            map.insert(i, i + 1);
+        }
+        for i in 0..5000u32 {
+            assert_eq!(map.get(i), Some(i + 1));
+        }
+    }
+
+    #[test]
+    fn secure_fixed_keys_deterministic() {
+        let seeded = || -> SecurePrimitiveMap<u64, u8> {
+            PrimitiveMap::dynamic_with_hasher(SipHasher::with_keys(7, 11))
+        };
+        let (a, b) = (seeded(), seeded());
+        let other: SecurePrimitiveMap<u64, u8> =
+            PrimitiveMap::dynamic_with_hasher(SipHasher::with_keys(7, 12));
+        for key in 0..1000u64 {
+            assert_eq!(a.get_addr(key), b.get_addr(key));
+        }
+        assert!((0..1000u64).any(|key| a.get_addr(key) != other.get_addr(key)));
+    }
+
+    #[test]
+    fn clone_from_reuses_buckets() {
+        let mut source = PrimitiveMap::dynamic();
+        for i in 0..10000u32 {
+            source.insert(i, i * 3);
+        }
+        let mut target = source.clone();
+        target.clear();
+        let capacity = target.capacity();
+
+        target.clone_from(&source);
+        assert_eq!(target.capacity(), capacity);
+        assert_eq!(target.len(), source.len());
+        assert_eq!(target.into_sorted_vec(), source.into_sorted_vec());
+    }
+
+    #[test]
+    fn sorted_vec_bucket_matches_small_vec_bucket() {
+        let mut sorted: PrimitiveMap<u16, u32, _, [SortedVecBucket<_, _>; 16], _> =
+            PrimitiveMap::custom(BucketList::empty(), DefaultHasher::default());
+        let mut unsorted: PrimitiveMap<u16, u32, _, [SmallVecBucket<_, _>; 16], _> =
+            PrimitiveMap::custom(BucketList::empty(), DefaultHasher::default());
+        for i in 0..2000u16 {
+            let key = i.wrapping_mul(40_503);
+            sorted.insert(key, u32::from(i));
+            unsorted.insert(key, u32::from(i));
+        }
+        for i in (0..4000u16).step_by(3) {
+            sorted.take(i);
+            unsorted.take(i);
+        }
+        for key in 0..u16::MAX {
+            assert_eq!(sorted.get(key), unsorted.get(key));
+        }
+    }
+
+    #[test]
+    fn entry_or_try_insert_with() {
+        let mut map = PrimitiveMap::dynamic();
+        let parse = |s: &str| s.parse::<u32>();
+
+        assert!(map.entry(1u32).or_try_insert_with(|| parse("x")).is_err());
+        assert_eq!(map.get(1), None);
+        assert!(map.is_empty());
+
+        assert_eq!(map.entry(1).or_try_insert_with(|| parse("12")), Ok(&mut 12));
+        assert_eq!(map.entry(1).or_try_insert_with(|| parse("x")), Ok(&mut 12));
+        assert_eq!(map.get(1), Some(12));
+    }
+
+    #[test]
+    fn perfect_map_no_collisions() {
+        let keys = [
+            3u32, 17, 256, 1024, 77, 9001, 42, 65_535, 12, 800_000, 5, 31, 64, 999, 123_456, 7,
+            4096, 2, 100, 31_337,
+        ];
+        let entries: Vec<(u32, u32)> = keys.iter().map(|&key| (key, key * 2)).collect();
+        let map = PerfectMap::build(&entries).unwrap();
+
+        assert_eq!(map.len(), keys.len());
+        for &key in &keys {
+            assert_eq!(map.get(key), Some(key * 2));
+        }
+        assert_eq!(map.get(8), None);
+        assert!(PerfectMap::build(&[(1u8, 1u8), (1, 2)]).is_none());
+    }
+
+    #[test]
+    fn reserve_buckets_bulk_load() {
+        let mut map = PrimitiveMap::dynamic();
+        let keys: Vec<u32> = (0..20_000u32).map(|i| i * 7).collect();
+        let mut counts = vec![0; BUCKET_LIST_SIZE];
+        for &key in &keys {
+            counts[map.bucket_index(key)] += 1;
+        }
+
+        map.reserve_buckets(&counts);
+        let capacity = map.capacity();
+        for &key in &keys {
+            map.insert(key, key + 1);
+        }
+        assert_eq!(map.capacity(), capacity);
+        for &key in &keys {
+            assert_eq!(map.get(key), Some(key + 1));
+        }
+    }
+
+    #[test]
+    fn max_by_and_min_by() {
+        let mut map = PrimitiveMap::dynamic();
+        assert_eq!(map.max_by(|a: &i32, b| a.cmp(b)), None);
+        for &(key, value) in &[(1u32, 5i32), (2, -3), (3, 12), (4, 7)] {
+            map.insert(key, value);
+        }
+        assert_eq!(map.max_by(|a, b| a.cmp(b)), Some((3, 12)));
+        assert_eq!(map.min_by(|a, b| a.cmp(b)), Some((2, -3)));
+        assert_eq!(
+            map.max_by(|a, b| a.abs().cmp(&b.abs()).reverse()),
+            Some((2, -3))
+        );
+    }
+
+    #[test]
+    fn atomic_map_concurrent_increments() {
+        use std::sync::atomic::Ordering;
+        use std::sync::Arc;
+        use std::thread;
+
+        let map = Arc::new(AtomicPrimitiveMap::new(vec![1u32, 2, 3, 2]));
+        assert_eq!(map.len(), 3);
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let map = Arc::clone(&map);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        map.increment(1);
+                        map.increment(if i % 2 == 0 { 2 } else { 3 });
+                        assert_eq!(map.increment(4), None);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(map.get(1).unwrap().load(Ordering::SeqCst), 8000);
+        assert_eq!(map.get(2).unwrap().load(Ordering::SeqCst), 4000);
+        assert_eq!(map.get(3).unwrap().load(Ordering::SeqCst), 4000);
+        assert!(map.get(4).is_none());
+    }
+
+    #[test]
+    fn stable_vec_bucket_keeps_order() {
+        let mut map: PrimitiveMap<u32, u32, _, [StableVecBucket<_, _>; 1], _> =
+            PrimitiveMap::custom(BucketList::empty(), DefaultHasher::default());
+        for key in 0..6u32 {
+            map.insert(key, key);
+        }
+        map.take(2);
+        let keys: Vec<u32> = map.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![0, 1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn get_or_insert_default_groups() {
+        let mut map = PrimitiveMap::dynamic();
+        let items = [(1u8, 10u32), (2, 5), (1, 7), (3, 1), (1, 3), (2, 2)];
+        for &(group, item) in &items {
+            let entry: &mut (usize, u32) = map.get_or_insert_default(group);
+            entry.0 += 1;
+            entry.1 += item;
+        }
+        assert_eq!(map.get(1), Some((3, 20)));
+        assert_eq!(map.get(2), Some((2, 7)));
+        assert_eq!(map.get(3), Some((1, 1)));
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn scan_is_empty_agrees_with_len() {
+        let mut map = PrimitiveMap::fixed();
+        assert!(map.scan_is_empty() && map.is_empty());
+
+        for i in 0..100u32 {
+            map.insert(i, i);
+        }
+        assert!(!map.scan_is_empty() && !map.is_empty());
+
+        for i in 0..99u32 {
+            map.take(i);
+        }
+        assert!(!map.scan_is_empty() && !map.is_empty());
+        map.take(99);
+        assert!(map.scan_is_empty() && map.is_empty());
+
+        map.insert(5, 5);
+        map.clear();
+        assert!(map.scan_is_empty() && map.is_empty());
+    }
+
+    #[test]
+    fn custom_array_full_load_with_hasher() {
+        let mut map: PrimitiveMap<u16, u16, _, [ArrayBucket<_, _>; 64], _> =
+            PrimitiveMap::custom(BucketList::empty(), SipHasher::with_keys(3, 5));
+        let full = 64 * BUCKET_SIZE as u16;
+        for key in 0..full {
+            map.insert(key, key * 2);
+        }
+        assert_eq!(map.len(), usize::from(full));
+        for key in 0..full {
+            assert_eq!(map.get(key), Some(key * 2));
+        }
+        assert!(map.try_insert(full, 0).is_err());
+    }
+
+    #[test]
+    fn drain_filter_partial() {
+        let mut map = PrimitiveMap::dynamic();
+        for i in 0..100u32 {
+            map.insert(i, i);
+        }
+
+        {
+            let mut drain = map.drain_filter(|key, value| {
+                *value += 1;
+                key % 2 == 0
+            });
+            for _ in 0..3 {
+                let (key, value) = drain.next().unwrap();
+                assert_eq!(key % 2, 0);
+                assert_eq!(value, key + 1);
+            }
+        }
+
+        assert_eq!(map.len(), 50);
+        for i in 0..100u32 {
+            let expected = if i % 2 == 0 { None } else { Some(i + 1) };
+            assert_eq!(map.get(i), expected);
+        }
+    }
+
+    #[test]
+    fn drain_filter_spilled_fixed() {
+        let mut map = PrimitiveMap::fixed();
+        for i in 0..4000u16 {
+            map.insert(i, i);
+        }
+        let drained = map.drain_filter(|key, _| key % 3 == 0).count();
+
+        assert_eq!(drained, 1334);
+        assert_eq!(map.len(), 4000 - 1334);
+        for i in 0..4000u16 {
+            let expected = if i % 3 == 0 { None } else { Some(i) };
+            assert_eq!(map.get(i), expected);
+        }
+    }
+
+    #[test]
+    fn reserve_with_growth_factor() {
+        let mut map =
+            PrimitiveMap::custom(SmallVecBucketList::with_len(64), DefaultHasher::default())
+                .with_growth_factor(1.5);
+        for i in 0..128u32 {
+            map.insert(i, i);
+        }
+
+        map.reserve(1);
+        assert_eq!(map.buckets.len(), 96);
+        map.reserve(100);
+        assert_eq!(map.buckets.len(), 144);
+        for i in 0..128u32 {
+            assert_eq!(map.get(i), Some(i));
+        }
+    }
+
+    #[test]
+    fn keys_sorted_ascending() {
+        let mut map = PrimitiveMap::fixed();
+        for i in (0..500u32).rev() {
+            map.insert(i * 7, i);
+        }
+
+        let keys: Vec<u32> = map.keys_sorted().collect();
+        assert_eq!(keys, (0..500).map(|i| i * 7).collect::<Vec<_>>());
+        assert_eq!(
+            map.keys_sorted().take(3).collect::<Vec<_>>(),
+            vec![0, 7, 14]
+        );
+    }
+
+    #[test]
+    fn bucket_find_mut_position() {
+        let mut bucket = SmallVecBucket::new();
+        for i in 0..5u32 {
+            bucket.push(i, i * 10);
+        }
+        let (idx, value) = bucket.find_mut(3).unwrap();
+        *value += 1;
+        assert_eq!(idx, 3);
+        assert_eq!(bucket.get(3), Some(31));
+        assert!(bucket.find_mut(9).is_none());
+
+        let mut map = PrimitiveMap::fixed();
+        map.insert(7u32, 1u32);
+        if let Entry::Occupied(mut entry) = map.entry(7) {
+            *entry.get_mut() += 1;
+            assert_eq!(entry.insert(5), 2);
+        } else {
+            panic!("entry should be occupied")
+        }
+        assert_eq!(map.get(7), Some(5));
+    }
+
+    #[test]
+    fn entry_remove_occupied() {
+        let mut map = PrimitiveMap::fixed();
+        for i in 0..4000u16 {
+            map.insert(i, i);
+        }
+
+        for i in (0..4000u16).step_by(2) {
+            match map.entry(i) {
+                Entry::Occupied(entry) => assert_eq!(entry.remove_entry(), (i, i)),
+                Entry::Vacant(_) => panic!("entry should be occupied"),
+            }
+        }
+        if let Entry::Occupied(entry) = map.entry(1) {
+            assert_eq!(entry.remove(), 1);
+        }
+
+        assert_eq!(map.len(), 1999);
+        for i in 0..4000u16 {
+            let expected = if i % 2 == 0 || i == 1 { None } else { Some(i) };
+            assert_eq!(map.get(i), expected);
+        }
+    }
+
+    #[test]
+    fn hash_key_is_stable() {
+        let map: PrimitiveMap<u32, u32, _, _, _> = PrimitiveMap::dynamic();
+        assert_eq!(map.hash_key(12345), map.hash_key(12345));
+        assert_ne!(map.hash_key(12345), map.hash_key(54321));
+        assert_eq!(map.hash_key(12345), DefaultHasher::default().hash(12345));
+    }
+
+    #[test]
+    fn fifo_map_evicts_oldest() {
+        let mut map = FifoPrimitiveMap::with_limit(4);
+        for i in 0..4u32 {
+            assert_eq!(map.insert(i, i), None);
+        }
+        assert_eq!(map.insert(0, 10), None);
+
+        assert_eq!(map.insert(4, 4), Some((0, 10)));
+        assert_eq!(map.insert(5, 5), Some((1, 1)));
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(0), None);
+        assert_eq!(map.get(1), None);
+        for i in 2..6u32 {
+            assert_eq!(map.get(i), Some(i));
+        }
+    }
+
+    #[test]
+    fn for_each_mut_caps_values() {
+        let mut map = PrimitiveMap::fixed();
+        for i in 0..3000u32 {
+            map.insert(i, i);
+        }
+
+        map.for_each_mut(|_, value| *value = (*value).min(1000));
+        for i in 0..3000u32 {
+            assert_eq!(map.get(i), Some(i.min(1000)));
+        }
+    }
+
+    #[test]
+    fn bucket_occupancy_tracks_live_entries() {
+        let mut bucket = ArrayBucket::new();
+        assert_eq!(bucket.occupancy().collect::<Vec<_>>(), vec![false, false]);
+        bucket.push(1u32, 1u32);
+        bucket.push(2, 2);
+        assert_eq!(bucket.occupancy().collect::<Vec<_>>(), vec![true, true]);
+        bucket.take(1);
+        assert_eq!(bucket.occupancy().collect::<Vec<_>>(), vec![true, false]);
+
+        let mut bucket = SmallVecBucket::new();
+        for i in 0..5u32 {
+            bucket.push(i, i);
+        }
+        bucket.take(3);
+        let occupancy: Vec<bool> = bucket.occupancy().collect();
+        assert_eq!(occupancy.len(), bucket.capacity());
+        assert_eq!(occupancy.iter().filter(|&&live| live).count(), 4);
+    }
+
+    #[test]
+    fn reserve_keeps_seeded_hasher() {
+        let mut map = PrimitiveMap::dynamic_with_hasher(SipHasher::with_keys(11, 13));
+        for i in 0..4096u64 {
+            map.insert(i, i);
+        }
+
+        map.reserve(10000);
+        assert!(map.buckets.len() > BUCKET_LIST_SIZE);
+        let seeded = SipHasher::with_keys(11, 13);
+        for i in 0..4096u64 {
+            assert_eq!(map.hash_key(i), seeded.hash(i));
+            assert_eq!(map.get(i), Some(i));
+        }
+    }
+
+    #[test]
+    fn soa_bucket_matches_small_vec_bucket() {
+        let mut soa = SoaBucket::new();
+        let mut vec = SmallVecBucket::new();
+        for i in 0..100u32 {
+            soa.push(i * 3, i);
+            vec.push(i * 3, i);
+        }
+        for i in (0..100u32).step_by(7) {
+            assert_eq!(soa.take(i * 3), vec.take(i * 3));
+        }
+        *soa.get_mut(6).unwrap() += 1;
+        *vec.get_mut(6).unwrap() += 1;
+
+        for key in 0..300u32 {
+            assert_eq!(soa.get(key), vec.get(key));
+        }
+        assert_eq!(
+            soa.iter().collect::<Vec<_>>(),
+            vec.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn insert_if_absent_keeps_existing() {
+        let mut map = PrimitiveMap::dynamic();
+        assert!(map.insert_if_absent(5u32, 1u32));
+        assert!(!map.insert_if_absent(5, 2));
+        assert_eq!(map.get(5), Some(1));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn default_hasher_compress_strategies() {
+        let mut mask = PrimitiveMap::dynamic_with_hasher(FastHasher::<u32, Mask>::default());
+        let mut fibonacci =
+            PrimitiveMap::dynamic_with_hasher(FastHasher::<u32, Fibonacci>::default());
+        for i in 0..5000u32 {
+            mask.insert(i << 16, i);
+            fibonacci.insert(i << 16, i);
+        }
+        for i in 0..5000u32 {
+            assert_eq!(mask.get(i << 16), Some(i));
+            assert_eq!(fibonacci.get(i << 16), Some(i));
+        }
+
+        assert_eq!(Mask::compress(0x1234, 256), 0x34);
+        assert_eq!(Modulo::compress(1000, 300), 100);
+        for hash in (0..1_000_000u64).step_by(997) {
+            assert!(Fibonacci::compress(hash, 300) < 300);
+        }
+        assert_eq!(
+            <DefaultHasher<u64> as Hasher<u64>>::compress(1000, 300),
+            Modulo::compress(1000, 300)
+        );
+    }
+
+    struct CountingHasher<'a> {
+        calls: &'a std::cell::Cell<usize>,
+    }
+
+    impl<'a> Hasher<u32> for CountingHasher<'a> {
+        fn hash(&self, value: u32) -> u32 {
+            self.calls.set(self.calls.get() + 1);
+            DefaultHasher::default().hash(value)
+        }
+
+        fn compress(hash: u32, upper_bound: usize) -> usize {
+            DefaultHasher::<u32>::compress(hash, upper_bound)
+        }
+    }
+
+    #[test]
+    fn get_or_insert_with_hashes_once() {
+        let calls = std::cell::Cell::new(0);
+        let mut map = PrimitiveMap::dynamic_with_hasher(CountingHasher { calls: &calls });
+
+        *map.get_or_insert_with(1u32, || 10u32) += 1;
+        assert_eq!(calls.get(), 1);
+        *map.get_or_insert_with(1, || 20) += 1;
+        assert_eq!(calls.get(), 2);
+        assert_eq!(map.get(1), Some(12));
+    }
+
+    #[test]
+    fn try_entry_on_full_map() {
+        let mut map = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; 4]>::empty(),
+            DefaultHasher::default(),
+        );
+        for i in 0..8u32 {
+            map.try_entry(i).unwrap().or_insert(i);
+        }
+
+        assert_eq!(map.try_entry(3).unwrap().get(), Some(3));
+        let err = map.try_entry(8).err().unwrap();
+        assert_eq!(err, CapacityError { key: 8, probes: 4 });
+    }
+
+    #[test]
+    fn retain_counts_removed() {
+        let mut bucket = StableVecBucket::new();
+        for i in 0..10u32 {
+            bucket.push(i, i);
+        }
+        assert_eq!(bucket.retain(|key, _| key % 3 == 0), 6);
+        assert_eq!(
+            bucket.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec![0, 3, 6, 9]
+        );
+
+        let mut map = PrimitiveMap::fixed();
+        for i in 0..4000u16 {
+            map.insert(i, i);
+        }
+        map.retain(|key, value| {
+            *value += 1;
+            key % 4 != 0
+        });
+        assert_eq!(map.len(), 3000);
+        for i in 0..4000u16 {
+            let expected = if i % 4 == 0 { None } else { Some(i + 1) };
+            assert_eq!(map.get(i), expected);
+        }
+    }
+
+    #[test]
+    fn shrink_to_fit_returns_inline() {
+        let mut bucket = SmallVecBucket::new();
+        for i in 0..10u32 {
+            bucket.push(i, i);
+        }
+        assert!(bucket.capacity() > BUCKET_SIZE);
+
+        for i in 1..10u32 {
+            bucket.take(i);
+        }
+        bucket.shrink_to_fit();
+        assert_eq!(bucket.capacity(), BUCKET_SIZE);
+        assert_eq!(bucket.get(0), Some(0));
+    }
+
+    #[test]
+    fn deterministic_same_buckets() {
+        let mut a = SecurePrimitiveMap::deterministic();
+        let b = SecurePrimitiveMap::<u64, u64>::deterministic();
+        for i in 0..1000u64 {
+            a.insert(i, i);
+            assert_eq!(a.bucket_index(i), b.bucket_index(i));
+        }
+        assert_eq!(a.hash_key(42), SipHasher::with_keys(0, 0).hash(42));
+    }
+
+    #[test]
+    fn bucket_insert_indexed() {
+        let mut bucket = SortedVecBucket::new();
+        for &key in &[5u32, 1, 9] {
+            let (idx, replaced) = bucket.insert_indexed(key, key);
+            assert_eq!(replaced, None);
+            assert_eq!(bucket.find_mut(key).unwrap().0, idx);
+        }
+        assert_eq!(bucket.insert_indexed(5, 50), (1, Some(5)));
+        assert_eq!(bucket.get(5), Some(50));
+
+        let mut bucket = ArrayBucket::new();
+        assert_eq!(bucket.insert_indexed(3u32, 3u32), (0, None));
+        assert_eq!(bucket.insert_indexed(4, 4), (1, None));
+        assert_eq!(bucket.insert_indexed(3, 30), (0, Some(3)));
+    }
+
+    #[test]
+    fn bucket_hint_avoids_regrowth() {
+        let mut map = PrimitiveMap::with_capacity_and_bucket_hint(256, 8);
+        let capacity = map.capacity();
+        assert_eq!(capacity, 256 * 8);
+
+        for i in 0..2048u32 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.get(2047), Some(2047));
+    }
+
+    #[test]
+    fn extend_from_slice_static() {
+        static PAIRS: &[(u16, u8)] = &[(1, 10), (300, 20), (7000, 30)];
+        let mut map = PrimitiveMap::fixed();
+        map.extend_from_slice(PAIRS);
+
+        assert_eq!(map.len(), 3);
+        for &(key, value) in PAIRS {
+            assert_eq!(map.get(key), Some(value));
+        }
+    }
+
+    thread_local! {
+        static SCANS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    fn count_scan() {
+        SCANS.with(|scans| scans.set(scans.get() + 1))
+    }
+
+    struct ScanCountingBucket(SmallVecBucket<u32, u32>);
+
+    impl Bucket<u32, u32> for ScanCountingBucket {
+        fn new() -> Self {
+            ScanCountingBucket(SmallVecBucket::new())
+        }
+
+        fn push(&mut self, key: u32, value: u32) {
+            self.0.push(key, value)
+        }
+
+        fn get(&self, key: u32) -> Option<u32> {
+            count_scan();
+            self.0.get(key)
+        }
+
+        fn get_mut(&mut self, key: u32) -> Option<&mut u32> {
+            count_scan();
+            self.0.get_mut(key)
+        }
+
+        fn find_mut(&mut self, key: u32) -> Option<(usize, &mut u32)> {
+            count_scan();
+            self.0.find_mut(key)
+        }
+
+        fn take(&mut self, key: u32) -> Option<(u32, u32)> {
+            count_scan();
+            self.0.take(key)
+        }
+
+        fn remove_at(&mut self, index: usize) -> (u32, u32) {
+            self.0.remove_at(index)
+        }
+
+        fn get_or_insert_with<F: FnOnce() -> u32>(&mut self, key: u32, f: F) -> &mut u32 {
+            count_scan();
+            self.0.get_or_insert_with(key, f)
+        }
+
+        fn clear(&mut self) {
+            self.0.clear()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.capacity()
+        }
+
+        fn reached_max_capacity(&self) -> bool {
+            false
+        }
+
+        fn iter(&self) -> impl Iterator<Item = (u32, u32)> {
+            self.0.iter()
+        }
+
+        fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = (u32, &'a mut u32)>
+        where
+            u32: 'a,
+        {
+            self.0.iter_mut()
+        }
+
+        fn retain<F: FnMut(u32, &mut u32) -> bool>(&mut self, f: F) -> usize {
+            self.0.retain(f)
+        }
+    }
+
+    #[test]
+    fn get_or_insert_with_scans_once() {
+        let mut map = PrimitiveMap::custom(
+            <[ScanCountingBucket; 16]>::empty(),
+            DefaultHasher::default(),
+        );
+
+        *map.get_or_insert_with(3, || 1) += 1;
+        assert_eq!(SCANS.with(|scans| scans.get()), 1);
+        *map.get_or_insert_with(3, || 5) += 1;
+        assert_eq!(SCANS.with(|scans| scans.get()), 2);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(3), Some(3));
+    }
+
+    #[test]
+    fn clear_and_shrink_releases_capacity() {
+        let mut map = PrimitiveMap::dynamic();
+        map.reserve(100_000);
+        for i in 0..100_000u32 {
+            map.insert(i, i);
+        }
+        let initial = PrimitiveMap::<u32, u32, _, _, _>::dynamic().capacity();
+        assert!(map.capacity() > initial);
+
+        map.clear_and_shrink();
+        assert_eq!(map.len(), 0);
+        assert_eq!(map.capacity(), initial);
+        map.insert(5, 5);
+        assert_eq!(map.get(5), Some(5));
+        assert_eq!(map.get(6), None);
+    }
+
+    #[test]
+    fn entry_bucket_index() {
+        let mut map = PrimitiveMap::dynamic();
+        map.insert(17u32, 1u32);
+        for key in 15..20u32 {
+            let home = map.bucket_index(key);
+            assert_eq!(map.entry(key).bucket_index(), home);
+        }
+    }
+
+    #[test]
+    fn value_histogram_bins() {
+        let mut map = PrimitiveMap::fixed();
+        for i in 0..100u16 {
+            map.insert(i, u32::from(i % 25));
+        }
+
+        let histogram = map.value_histogram(10);
+        assert_eq!(histogram.len(), 3);
+        assert_eq!(histogram.get(0), Some(40));
+        assert_eq!(histogram.get(10), Some(40));
+        assert_eq!(histogram.get(20), Some(20));
+    }
+
+    #[test]
+    fn map_eq_fast_and_slow_path() {
+        let mut a = PrimitiveMap::dynamic();
+        let mut b = PrimitiveMap::dynamic();
+        for i in 0..1000u32 {
+            a.insert(i, i);
+            b.insert(999 - i, 999 - i);
+        }
+        assert!(a == b);
+        b.insert(1000, 0);
+        assert!(a != b);
+
+        // Keys 0, 4 and 8 collide, so insertion order decides which of them
+        // spills into the next bucket.
+        let new = || {
+            PrimitiveMap::custom(
+                <[ArrayBucket<u32, u32>; 4]>::empty(),
+                DefaultHasher::default(),
+            )
+        };
+        let mut a = new();
+        let mut b = new();
+        for &key in &[0, 4, 8] {
+            a.insert(key, key);
+        }
+        for &key in &[8, 4, 0] {
+            b.insert(key, key);
+        }
+        assert!(!a.buckets[0].eq_entries(&b.buckets[0]));
+        assert!(a == b);
+        *b.get_or_insert_default(4) += 1;
+        assert!(a != b);
+    }
+
+    #[test]
+    fn grow_to_larger_array() {
+        let mut small = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; 64]>::empty(),
+            DefaultHasher::default(),
+        );
+        for i in 0..128u32 {
+            small.insert(i, i * 2);
+        }
+        assert!(small.try_insert(128, 0).is_err());
+
+        let mut large: PrimitiveMap<_, _, _, [ArrayBucket<u32, u32>; 1024], _> = small.grow_to();
+        assert_eq!(large.len(), 128);
+        for i in 0..128u32 {
+            assert_eq!(large.get(i), Some(i * 2));
+        }
+        large.insert(128, 0);
+        assert_eq!(large.get(128), Some(0));
+    }
+
+    #[test]
+    fn bucket_drain_filter() {
+        let mut bucket = SoaBucket::new();
+        for i in 0..10u32 {
+            bucket.push(i, i * 10);
+        }
+
+        let drained: Vec<_> = bucket.drain_filter(|key, _| key >= 7).collect();
+        assert_eq!(drained, vec![(7, 70), (8, 80), (9, 90)]);
+        assert_eq!(bucket.iter().count(), 7);
+        assert_eq!(bucket.get(6), Some(60));
+        assert_eq!(bucket.get(7), None);
+    }
+
+    #[test]
+    fn key_range_direct_index() {
+        let map: PrimitiveMap<u8, u8, _, _, _> = PrimitiveMap::fixed();
+        assert!(map.covers_key_range());
+        let mut seen = vec![false; BUCKET_LIST_SIZE];
+        for key in 0..=255u8 {
+            let idx = map.bucket_index(key);
+            assert!(!seen[idx]);
+            seen[idx] = true;
+        }
+
+        let map: PrimitiveMap<i8, u8, _, _, _> = PrimitiveMap::fixed();
+        assert!(map.covers_key_range());
+        let map: PrimitiveMap<u16, u8, _, _, _> = PrimitiveMap::fixed();
+        assert!(!map.covers_key_range());
+    }
+
+    #[test]
+    fn modify_or_insert_runs_only_on_existing() {
+        let mut map = PrimitiveMap::dynamic();
+        let mut calls = 0;
+        map.modify_or_insert(1u32, 10u32, |value| {
+            calls += 1;
+            *value *= 2
+        });
+        assert_eq!(calls, 0);
+        assert_eq!(map.get(1), Some(10));
+
+        map.modify_or_insert(1, 10, |value| {
+            calls += 1;
+            *value *= 2
+        });
+        assert_eq!(calls, 1);
+        assert_eq!(map.get(1), Some(20));
+    }
+
+    #[test]
+    fn overflow_wraps_to_first_bucket() {
+        let mut map = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; 4]>::empty(),
+            DefaultHasher::default(),
+        );
+        let keys = [3u32, 7, 11, 15];
+        for &key in &keys {
+            assert_eq!(map.bucket_index(key), 3);
+            map.insert(key, key);
+        }
+
+        assert!(map.buckets[1].is_empty());
+        assert_eq!(map.buckets[0].iter().count(), 2);
+        for &key in &keys {
+            assert_eq!(map.get(key), Some(key));
+        }
+        map.take(3);
+        assert_eq!(map.buckets[3].iter().count(), 2);
+        assert_eq!(map.get(15), Some(15));
+    }
+
+    #[test]
+    fn finalized_spreads_strided_keys() {
+        let distinct = |indices: Vec<usize>| {
+            let mut indices = indices;
+            indices.sort_unstable();
+            indices.dedup();
+            indices.len()
+        };
+        let raw = PrimitiveMap::<u32, u32, _, _, _>::dynamic_with_hasher(
+            FastHasher::<u32, Mask>::default(),
+        );
+        let finalized =
+            PrimitiveMap::<u32, u32, _, _, _>::dynamic_with_hasher(Finalized::new(FastHasher::<
+                u32,
+                Mask,
+            >::default(
+            )));
+
+        let keys = (0..1000u32).map(|i| i << 16);
+        assert!(distinct(keys.clone().map(|key| raw.bucket_index(key)).collect()) <= 4);
+        assert!(distinct(keys.map(|key| finalized.bucket_index(key)).collect()) > 700);
+    }
+
+    #[test]
+    fn atomic_map_load_is_monotonic() {
+        use std::sync::atomic::Ordering;
+        use std::thread;
+
+        let map = AtomicPrimitiveMap::new(0..16u32);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..10_000 {
+                    map.increment(7);
+                }
+            });
+            scope.spawn(|| {
+                let mut last = 0;
+                for _ in 0..10_000 {
+                    let current = map.load(7, Ordering::Relaxed).unwrap();
+                    assert!(current >= last);
+                    last = current;
+                }
+            });
+        });
+
+        assert_eq!(map.load(7, Ordering::Relaxed), Some(10_000));
+        assert_eq!(map.load(16, Ordering::Relaxed), None);
+    }
+
+    #[test]
+    fn resize_callback_fires_on_growth() {
+        use std::sync::Mutex;
+
+        let resizes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = resizes.clone();
+        let mut map = PrimitiveMap::dynamic()
+            .with_resize_callback(move |old, new| recorded.lock().unwrap().push((old, new)));
+        for i in 0..20_000u32 {
+            map.reserve(1);
+            map.insert(i, i);
+        }
+        assert_eq!(
+            *resizes.lock().unwrap(),
+            vec![(2048, 4096), (4096, 8192), (8192, 16384)]
+        );
+        assert_eq!(map.get(19_999), Some(19_999));
+
+        for i in 10..20_000u32 {
+            map.take(i);
+        }
+        map.shrink_if_sparse();
+        assert_eq!(resizes.lock().unwrap().last(), Some(&(16384, 2048)));
+
+        map.reserve(20_000);
+        map.clear_and_shrink();
+        assert_eq!(resizes.lock().unwrap().last(), Some(&(16384, 2048)));
+        assert_eq!(resizes.lock().unwrap().len(), 6);
+        map.clear_and_shrink();
+        assert_eq!(resizes.lock().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn keys_only_set() {
+        use std::mem::size_of;
+
+        assert_eq!(size_of::<(u32, ())>(), size_of::<u32>());
+        assert_eq!(
+            size_of::<KeysOnlyBucket<u32>>(),
+            size_of::<SmallVecBucket<u32, ()>>()
+        );
+
+        let mut set = PrimitiveSet::new();
+        for i in 0..5000u32 {
+            assert!(set.insert(i * 2));
+        }
+        assert!(!set.insert(0));
+        assert_eq!(set.len(), 5000);
+        assert!(set.contains(10));
+        assert!(!set.contains(11));
+        set.reserve(10_000);
+        assert!(set.contains(9998));
+        assert!(set.remove(9998));
+        assert!(!set.contains(9998));
+    }
+
+    #[test]
+    fn get_unchecked_present() {
+        let mut map = PrimitiveMap::fixed();
+        for i in 0..3000u16 {
+            map.insert(i, i * 2);
+        }
+        for i in 0..3000u16 {
+            assert_eq!(unsafe { map.get_unchecked(i) }, i * 2);
+        }
+
+        // Near full, so that lookups have to probe past the home bucket.
+        let mut map = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; 64]>::empty(),
+            DefaultHasher::default(),
+        );
+        for i in 0..120u32 {
+            map.insert(i * 7, i);
+        }
+        for i in 0..120u32 {
+            assert_eq!(unsafe { map.get_unchecked(i * 7) }, i);
+        }
+    }
+
+    #[test]
+    fn capacity_for_const_sizes_arrays() {
+        const BUCKETS: usize = capacity_for_const(50, 3, 4);
+        assert_eq!(BUCKETS, 34);
+        assert_eq!(capacity_for_const(100, 1, 1), 50);
+        assert_eq!(capacity_for_const(0, 3, 4), 0);
+
+        let mut map = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; capacity_for_const(50, 3, 4)]>::empty(),
+            DefaultHasher::default(),
+        );
+        for i in 0..50u32 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 50);
+    }
+
+    #[test]
+    fn bucket_extend_dedups() {
+        let mut bucket = SmallVecBucket::new();
+        bucket.push(1u32, 1u32);
+        bucket.extend(vec![(2, 2), (1, 10), (3, 3), (2, 20)]);
+
+        let mut entries: Vec<_> = bucket.iter().collect();
+        entries.sort_unstable();
+        assert_eq!(entries, vec![(1, 10), (2, 20), (3, 3)]);
+    }
+
+    #[test]
+    fn from_raw_buckets_round_trip() {
+        let mut map = PrimitiveMap::fixed_with_hasher(SipHasher::with_keys(1, 2));
+        for i in 0..4000u16 {
+            map.insert(i, i);
+        }
+
+        let buckets = *map.raw_buckets();
+        let restored = unsafe {
+            PrimitiveMap::from_raw_buckets(buckets, map.len(), SipHasher::with_keys(1, 2))
+        };
+        assert_eq!(restored.len(), 4000);
+        for i in 0..4000u16 {
+            assert_eq!(restored.get(i), Some(i));
+        }
+        assert!(restored == map);
+    }
+
+    #[test]
+    fn get_many_mut_distinct_and_overlapping() {
+        let mut map = PrimitiveMap::fixed();
+        for i in 0..4000u16 {
+            map.insert(i, i);
+        }
+
+        {
+            let values = map.get_many_mut(&[3999, 5, 4100, 6]).unwrap();
+            assert!(values[2].is_none());
+            for value in values.into_iter().flatten() {
+                *value += 1;
+            }
+        }
+        assert_eq!(map.get(3999), Some(4000));
+        assert_eq!(map.get(5), Some(6));
+        assert_eq!(map.get(6), Some(7));
+
+        assert_eq!(
+            map.get_many_mut(&[1, 2, 1]).err(),
+            Some(OverlapError { key: 1 })
+        );
+    }
+
+    #[test]
+    fn counting_bucket_clustered_inserts() {
+        let mut map = PrimitiveMap::custom(
+            smallvec::SmallVec::<
+                [CountingBucket<SmallVecBucket<CountedKey<u32>, u32>>; BUCKET_LIST_SIZE],
+            >::empty(),
+            DefaultHasher::default(),
+        );
+        let clustered: Vec<u32> = (0..u32::MAX)
+            .filter(|&key| map.bucket_index(key) == 0)
+            .take(50)
+            .collect();
+        assert_eq!(clustered.len(), 50);
+        for &key in &clustered {
+            map.insert_if_absent(key, key);
+        }
+
+        // The n-th insert compares against the n - 1 keys already there.
+        assert_eq!(map.comparisons(), 49 * 50 / 2);
+        map.reset_comparisons();
+        map.insert_if_absent(1, 1);
+        assert_eq!(map.comparisons(), 0);
+    }
+
+    #[test]
+    fn into_iter_array_map_without_heap() {
+        use smallvec::SmallVec;
+
+        let mut map = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; 8]>::empty(),
+            DefaultHasher::default(),
+        );
+        for i in 0..16u32 {
+            map.insert(i * 3, i);
+        }
+
+        let mut entries: SmallVec<[(u32, u32); 16]> = map.into_iter().collect();
+        assert!(!entries.spilled());
+        entries.sort_unstable();
+        let expected: Vec<(u32, u32)> = (0..16).map(|i| (i * 3, i)).collect();
+        assert_eq!(&entries[..], &expected[..]);
+    }
+
+    #[test]
+    fn auto_shrink_after_bulk_removal() {
+        let mut map = PrimitiveMap::dynamic().with_auto_shrink(true);
+        map.reserve(100_000);
+        for i in 0..100_000u32 {
+            map.insert(i, i);
+        }
+        let capacity = map.capacity();
+
+        map.retain(|key, _| key % 10 == 0);
+        assert!(map.capacity() < capacity / 2);
+        assert_eq!(map.buckets.len(), 16384);
+        assert_eq!(map.len(), 10_000);
+        for i in 0..100_000u32 {
+            let expected = if i % 10 == 0 { Some(i) } else { None };
+            assert_eq!(map.get(i), expected);
+        }
+
+        let mut manual = PrimitiveMap::dynamic();
+        manual.reserve(100_000);
+        for i in 0..100_000u32 {
+            manual.insert(i, i);
+        }
+        manual.retain(|key, _| key % 10 == 0);
+        assert_eq!(manual.capacity(), capacity);
+    }
+
+    #[test]
+    fn get_with_probe_near_full() {
+        let mut map = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; 64]>::empty(),
+            DefaultHasher::default(),
+        );
+        for i in 0..120u32 {
+            map.insert(i * 7, i);
+        }
+
+        let probes: Vec<usize> = (0..120u32)
+            .map(|i| {
+                let (value, probes) = map.get_with_probe(i * 7).unwrap();
+                assert_eq!(value, i);
+                probes
+            })
+            .collect();
+        assert!(probes.iter().any(|&probes| probes > 1));
+        assert_eq!(map.get_with_probe(1), None);
+    }
+
+    #[test]
+    fn keys_and_values_where() {
+        let mut map = PrimitiveMap::dynamic();
+        for i in 0..20u32 {
+            map.insert(i, i * 10);
+        }
+
+        let mut keys: Vec<u32> = map.keys_where(|_, value| value > 150).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![16, 17, 18, 19]);
+
+        let mut values: Vec<u32> = map.values_where(|key, _| key < 3).collect();
+        values.sort_unstable();
+        assert_eq!(values, vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn bucket_remove_at() {
+        let mut bucket = SmallVecBucket::new();
+        for i in 0..4u32 {
+            bucket.push(i, i * 10);
+        }
+
+        let (idx, _) = bucket.find_mut(2).unwrap();
+        assert_eq!(bucket.remove_at(idx), (2, 20));
+        assert_eq!(bucket.get(2), None);
+        assert_eq!(bucket.iter().count(), 3);
+
+        let mut bucket = ArrayBucket::new();
+        bucket.push(1u32, 10u32);
+        bucket.push(2, 20);
+        assert_eq!(bucket.remove_at(0), (1, 10));
+        assert_eq!(bucket.iter().collect::<Vec<_>>(), vec![(2, 20)]);
+    }
+
+    #[test]
+    fn set_display() {
+        let mut set = PrimitiveSet::new();
+        assert_eq!(set.to_string(), "{}");
+
+        assert!(set.insert(1u32));
+        assert!(!set.insert(1));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.to_string(), "{1}");
+
+        set.insert(2);
+        set.insert(3);
+        let formatted = set.to_string();
+        assert!(formatted.starts_with('{') && formatted.ends_with('}'));
+        let mut elements: Vec<&str> = formatted[1..formatted.len() - 1].split(", ").collect();
+        elements.sort_unstable();
+        assert_eq!(elements, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn contains_key_agrees_with_get() {
+        let mut map = PrimitiveMap::custom(
+            smallvec::SmallVec::<[SoaBucket<u32, u32>; BUCKET_LIST_SIZE]>::empty(),
+            DefaultHasher::default(),
+        );
+        let mut fixed = PrimitiveMap::fixed();
+        for i in 0..2000u32 {
+            map.insert(i * 3, i);
+            fixed.insert(i as u16 * 3, i);
+        }
+
+        for key in 0..7000u32 {
+            assert_eq!(map.contains_key(key), map.get(key).is_some());
+            assert_eq!(
+                fixed.contains_key(key as u16),
+                fixed.get(key as u16).is_some()
+            );
+        }
+    }
+
+    // Sends every key to the first bucket.
+    #[derive(Clone, Copy)]
+    struct Constant;
+
+    impl Hasher<u32> for Constant {
+        fn hash(&self, _: u32) -> u32 {
+            0
+        }
+
+        fn compress(hash: u32, upper_bound: usize) -> usize {
+            hash as usize % upper_bound
+        }
+    }
+
+    #[test]
+    fn btree_bucket_under_one_hash() {
+        let mut btree = PrimitiveMap::custom(
+            smallvec::SmallVec::<[BTreeBucket<u32, u32>; BUCKET_LIST_SIZE]>::empty(),
+            Constant,
+        );
+        let mut vec = PrimitiveMap::custom(SmallVecBucketList::empty(), Constant);
+        for i in 0..3000u32 {
+            let key = i.wrapping_mul(2_654_435_761);
+            btree.insert(key, i);
+            vec.insert(key, i);
+        }
+        for i in (0..3000u32).step_by(3) {
+            let key = i.wrapping_mul(2_654_435_761);
+            assert_eq!(btree.take(key), vec.take(key));
+        }
+
+        assert_eq!(btree.len(), vec.len());
+        let mut entries: Vec<(u32, u32)> = vec.iter().collect();
+        entries.sort_unstable();
+        assert_eq!(btree.into_sorted_vec(), entries);
+    }
+
+    #[test]
+    fn flat_map_expands_entries() {
+        let mut map = PrimitiveMap::dynamic();
+        for i in 0..100u32 {
+            map.insert(i, i * 3);
+        }
+
+        let expanded = map.flat_map(|key, value| {
+            vec![
+                (key * 2, u64::from(value)),
+                (key * 2 + 1, u64::from(value) + 1),
+            ]
+        });
+        assert_eq!(expanded.len(), 200);
+        for i in 0..100u32 {
+            assert_eq!(expanded.get(i * 2), Some(u64::from(i * 3)));
+            assert_eq!(expanded.get(i * 2 + 1), Some(u64::from(i * 3) + 1));
+        }
+
+        let mut map = PrimitiveMap::dynamic();
+        map.insert(1u32, 10u32);
+        map.insert(2, 20);
+        let collapsed = map.flat_map(|_, value| Some((0, value)));
+        assert_eq!(collapsed.len(), 1);
+    }
+
+    #[test]
+    fn bucket_entry_index() {
+        let mut bucket = SmallVecBucket::<u32, u32>::new();
+        assert_eq!(bucket.entry_index(5), (0, true));
+        assert_eq!(bucket.entry_index(7), (1, true));
+        assert_eq!(bucket.entry_index(5), (0, false));
+        assert_eq!(bucket.get(7), Some(0));
+
+        let mut sorted = SortedVecBucket::<u32, u32>::new();
+        assert_eq!(sorted.entry_index(5), (0, true));
+        assert_eq!(sorted.entry_index(3), (0, true));
+        assert_eq!(sorted.entry_index(5), (1, false));
+        assert_eq!(sorted.iter().count(), 2);
+    }
+
+    #[test]
+    fn btree_bucket_keeps_duplicates() {
+        let mut map = PrimitiveMap::custom(
+            smallvec::SmallVec::<[BTreeBucket<u32, u32>; BUCKET_LIST_SIZE]>::empty(),
+            DefaultHasher::default(),
+        );
+        map.insert(1, 10);
+        map.insert(1, 20);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(1), Some(10));
+
+        assert_eq!(map.take(1), Some((1, 10)));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(1), Some(20));
+        assert_eq!(map.take(1), Some((1, 20)));
+        assert!(map.is_empty());
+        assert!(map.scan_is_empty());
+        assert_eq!(map.take(1), None);
+    }
+
+    #[test]
+    fn mask_with_odd_bucket_count() {
+        let mut map = PrimitiveMap::dynamic_with_hasher(FastHasher::<u32, Mask>::default());
+        map.reserve_exact(5000);
+        assert!(!map.buckets.len().is_power_of_two());
+        for i in 0..5000u32 {
+            map.insert(i, i);
+        }
+        for i in 0..5000u32 {
+            assert_eq!(map.get(i), Some(i));
+        }
+        assert_eq!(Mask::compress(1000, 3), 1);
+    }
+
+    #[test]
+    fn retain_with_spilled_duplicates() {
+        let mut map = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; 64]>::empty(),
+            DefaultHasher::default(),
+        );
+        for i in 0..100u32 {
+            map.insert(i * 7, i);
+        }
+        for i in 0..20u32 {
+            map.insert(i * 7, i + 1000);
+        }
+
+        map.retain(|_, &mut value| value % 2 == 0);
+        assert_eq!(map.len(), map.iter().count());
+        for (key, value) in map.iter() {
+            assert_eq!(value % 2, 0);
+            assert!(map.contains_key(key));
+        }
+        for i in (0..100u32).filter(|i| i % 2 == 0) {
+            assert!(map.contains_key(i * 7));
+        }
+    }
+
+    #[test]
+    fn occupied_entry_removes_by_index() {
+        let mut map = PrimitiveMap::custom(
+            smallvec::SmallVec::<
+                [CountingBucket<SmallVecBucket<CountedKey<u32>, u32>>; BUCKET_LIST_SIZE],
+            >::empty(),
+            DefaultHasher::default(),
+        );
+        for i in 0..10_000u32 {
+            map.insert(i, i * 2);
+        }
+
+        map.reset_comparisons();
+        assert!(map.entry(1234).is_occupied());
+        let lookup = map.comparisons();
+
+        map.reset_comparisons();
+        match map.entry(1234) {
+            Entry::Occupied(entry) => assert_eq!(entry.remove_entry(), (1234, 2468)),
+            Entry::Vacant(_) => panic!("1234 is in the map"),
+        }
+        assert_eq!(map.comparisons(), lookup);
+        assert_eq!(map.len(), 9999);
+        assert_eq!(map.get(1234), None);
+    }
+
+    #[test]
+    fn counting_bucket_counts_binary_search() {
+        let mut bucket = CountingBucket::<SortedVecBucket<CountedKey<u32>, u32>>::new();
+        for i in 0..64u32 {
+            bucket.push(i, i);
+        }
+
+        bucket.reset_comparisons();
+        assert_eq!(bucket.get(63), Some(63));
+        assert!(bucket.comparisons() > 0 && bucket.comparisons() <= 7);
+    }
+
+    #[test]
+    fn btree_bucket_entry_under_one_hash() {
+        let mut map = PrimitiveMap::custom(
+            smallvec::SmallVec::<[BTreeBucket<u32, u32>; BUCKET_LIST_SIZE]>::empty(),
+            Constant,
+        );
+        for i in 0..20_000u32 {
+            *map.entry(i).or_insert(0) += i;
+        }
+        for i in 0..20_000u32 {
+            *map.entry(i).or_insert(0) += 1;
+        }
+        assert_eq!(map.len(), 20_000);
+        assert_eq!(map.get(777), Some(778));
+
+        for i in (0..20_000u32).step_by(2) {
+            match map.entry(i) {
+                Entry::Occupied(entry) => assert_eq!(entry.remove(), i + 1),
+                Entry::Vacant(_) => panic!("{} is in the map", i),
+            }
+        }
+        assert_eq!(map.len(), 10_000);
+        assert_eq!(map.get(776), None);
+        assert_eq!(map.get(777), Some(778));
+    }
+
+    #[test]
+    fn value_histogram_negative_values() {
+        let mut map = PrimitiveMap::dynamic();
+        for (key, &value) in [-15i32, -10, -3, 0, 3, 9, 10].iter().enumerate() {
+            map.insert(key as u32, value);
+        }
+
+        let histogram = map.value_histogram(10);
+        assert_eq!(histogram.get(-20), Some(1));
+        assert_eq!(histogram.get(-10), Some(2));
+        assert_eq!(histogram.get(0), Some(3));
+        assert_eq!(histogram.get(10), Some(1));
+        assert_eq!(histogram.len(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "bin_width must be positive")]
+    fn value_histogram_zero_width() {
+        let mut map = PrimitiveMap::dynamic();
+        map.insert(1u32, 5u32);
+        map.value_histogram(0);
+    }
+
+    #[test]
+    fn drain_filter_leaked_after_one_entry() {
+        let mut map = PrimitiveMap::custom(
+            <[ArrayBucket<u32, u32>; 4]>::empty(),
+            DefaultHasher::default(),
+        );
+        for &key in &[0, 4, 8] {
+            map.insert(key, key);
+        }
+        assert_eq!(map.bucket_index(4), map.bucket_index(0));
+        assert_eq!(map.bucket_index(8), map.bucket_index(0));
+
+        let mut drain = map.drain_filter(|key, _| key < 8);
+        assert!(drain.next().is_some());
+        mem::forget(drain);
+
+        assert_eq!(map.len(), map.iter().count());
+        assert_eq!(map.get(8), Some(8));
+        for (key, value) in map.iter() {
+            assert_eq!(map.get(key), Some(value));
+        }
+    }
+
+    #[test]
+    fn perfect_map_many_keys() {
+        let entries: Vec<(u32, u32)> = (0..5000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761), i))
+            .collect();
+        let map = PerfectMap::build(&entries).unwrap();
+
+        assert_eq!(map.len(), 5000);
+        for &(key, value) in &entries {
+            assert_eq!(map.get(key), Some(value));
+        }
+        assert_eq!(map.get(1), None);
+        assert!(PerfectMap::<u32, u32>::build(&[]).unwrap().is_empty());
+    }
 }
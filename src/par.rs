@@ -0,0 +1,18 @@
+use rayon::prelude::*;
+
+use {Bucket, BucketList, Hasher, Key, PrimitiveMap, Value};
+
+impl<K, V, B, BL, H> PrimitiveMap<K, V, B, BL, H>
+where
+    K: Key + Send + Sync,
+    V: Value + Send + Sync,
+    B: Bucket<K, V> + Sync,
+    BL: BucketList<K, V, Bucket = B> + Sync,
+    H: Hasher<K> + Sync,
+{
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (K, V)> + '_ {
+        (0..self.buckets.len())
+            .into_par_iter()
+            .flat_map_iter(move |idx| self.buckets.get(idx).iter())
+    }
+}
@@ -0,0 +1,124 @@
+use kv::{IntegerKey, Value};
+
+// A first level with more than this many slots per key is retried with
+// another multiplier. The expected total is below 2 per key, so few
+// retries are needed.
+const MAX_SLOTS_PER_KEY: usize = 4;
+
+pub struct PerfectMap<K, V> {
+    multiplier: u64,
+    groups: Vec<Group>,
+    slots: Vec<Option<(K, V)>>,
+    len: usize,
+}
+
+// The keys of one first level index, placed into `size` slots from
+// `offset` on with a multiplier of their own.
+#[derive(Clone, Copy)]
+struct Group {
+    offset: usize,
+    size: usize,
+    multiplier: u64,
+}
+
+impl<K: IntegerKey, V: Value> PerfectMap<K, V> {
+    /// Builds a two level table in which every key has a slot of its own,
+    /// so that `get` is two indexes with no probing. The first level hashes
+    /// the `n` keys into `n` groups, a group of `b` keys then gets `b * b`
+    /// slots and a multiplier that separates them. That takes at most
+    /// `4 * n` slots plus one small header per key, and an expected
+    /// constant number of attempts per level. Returns `None` if `entries`
+    /// contains the same key twice.
+    pub fn build(entries: &[(K, V)]) -> Option<Self> {
+        let mut keys: Vec<u64> = entries.iter().map(|&(key, _)| key.to_u64()).collect();
+        keys.sort_unstable();
+        if keys.windows(2).any(|pair| pair[0] == pair[1]) {
+            return None;
+        }
+
+        let len = entries.len().max(1);
+        let mut multiplier = 0x9e37_79b9_7f4a_7c15u64;
+        let grouped = loop {
+            let mut grouped: Vec<Vec<(K, V)>> = vec![Vec::new(); len];
+            for &(key, value) in entries {
+                grouped[index(key, multiplier, len)].push((key, value));
+            }
+            let slots: usize = grouped.iter().map(|group| group.len() * group.len()).sum();
+            if slots <= MAX_SLOTS_PER_KEY * len {
+                break grouped;
+            }
+            multiplier = next_multiplier(multiplier);
+        };
+
+        let mut groups = Vec::with_capacity(len);
+        let mut slots = Vec::new();
+        let mut group_multiplier = next_multiplier(multiplier);
+        for entries in &grouped {
+            let size = entries.len() * entries.len();
+            let placed = loop {
+                if let Some(placed) = Self::place(entries, group_multiplier, size) {
+                    break placed;
+                }
+                group_multiplier = next_multiplier(group_multiplier);
+            };
+            groups.push(Group {
+                offset: slots.len(),
+                size,
+                multiplier: group_multiplier,
+            });
+            slots.extend(placed);
+        }
+
+        Some(PerfectMap {
+            multiplier,
+            groups,
+            slots,
+            len: entries.len(),
+        })
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        let group = self.groups[index(key, self.multiplier, self.groups.len())];
+        if group.size == 0 {
+            return None;
+        }
+        match self.slots[group.offset + index(key, group.multiplier, group.size)] {
+            Some((k, value)) if k == key => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn place(entries: &[(K, V)], multiplier: u64, size: usize) -> Option<Vec<Option<(K, V)>>> {
+        let mut slots = vec![None; size];
+        for &(key, value) in entries {
+            let slot = &mut slots[index(key, multiplier, size)];
+            if slot.is_some() {
+                return None;
+            }
+            *slot = Some((key, value));
+        }
+        Some(slots)
+    }
+}
+
+#[inline]
+fn index<K: IntegerKey>(key: K, multiplier: u64, size: usize) -> usize {
+    ((key.to_u64().wrapping_mul(multiplier) >> 32) % size as u64) as usize
+}
+
+// xorshift step, forced odd so the multiplication stays a bijection
+fn next_multiplier(multiplier: u64) -> u64 {
+    let mut x = multiplier;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x | 1
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+
+use bucket::{KeysOnlyBucket, KeysOnlyBucketList};
+use hash::{DefaultHasher, Hasher};
+use kv::Key;
+use PrimitiveMap;
+
+type InnerMap<K> = PrimitiveMap<K, (), KeysOnlyBucket<K>, KeysOnlyBucketList<K>, DefaultHasher<K>>;
+
+/// A set of keys stored in `KeysOnlyBucket`s. Unlike the map's `insert`,
+/// inserting a key that is already present leaves the set unchanged.
+pub struct PrimitiveSet<K: Key>
+where
+    DefaultHasher<K>: Hasher<K>,
+{
+    map: InnerMap<K>,
+}
+
+impl<K: Key> PrimitiveSet<K>
+where
+    DefaultHasher<K>: Hasher<K>,
+{
+    pub fn new() -> Self {
+        PrimitiveSet {
+            map: PrimitiveMap::keys_only(),
+        }
+    }
+
+    /// Returns whether `key` was newly inserted.
+    pub fn insert(&mut self, key: K) -> bool {
+        self.map.insert_if_absent(key, ())
+    }
+
+    pub fn contains(&self, key: K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Returns whether `key` was present.
+    pub fn remove(&mut self, key: K) -> bool {
+        self.map.take(key).is_some()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = K> + '_ {
+        self.map.iter().map(|(key, _)| key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<K: Key> Default for PrimitiveSet<K>
+where
+    DefaultHasher<K>: Hasher<K>,
+{
+    fn default() -> Self {
+        PrimitiveSet::new()
+    }
+}
+
+/// Formats the set as `{1, 2, 3}`, in iteration order.
+impl<K: Key + fmt::Display> fmt::Display for PrimitiveSet<K>
+where
+    DefaultHasher<K>: Hasher<K>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+        for (idx, key) in self.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", key)?;
+        }
+        write!(f, "}}")
+    }
+}